@@ -1,28 +1,81 @@
 use std;
-use {SystrayError};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use {SystrayError, SystrayEvent, Capabilities, IconResource, MenuItemKind, TrayConfig};
+use api::{BackendWindow, TimerTick};
 
 pub struct Window {
 }
 
 impl Window {
-    pub fn new() -> Result<Window, SystrayError> {
+    pub fn new(_: Sender<SystrayEvent>, _: &TrayConfig) -> Result<Window, SystrayError> {
         Err(SystrayError::NotImplementedError)
     }
-    pub fn quit(&self) {
-        unimplemented!()
-    }
-    pub fn set_tooltip(&self, _: &String) -> Result<(), SystrayError> {
-        unimplemented!()
-    }
     pub fn add_menu_item<F>(&self, _: &String, _: F) -> Result<u32, SystrayError>
         where F: std::ops::Fn(&Window) -> () + 'static
     {
-        unimplemented!()
+        Err(SystrayError::NotImplementedError)
     }
-    pub fn wait_for_message(&mut self) {
-        unimplemented!()
+    pub fn wait_for_message(&mut self) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
     }
     pub fn set_icon_from_buffer(&self, _: &[u8], _: u32, _: u32) -> Result<(), SystrayError> {
-        unimplemented!()
+        Err(SystrayError::NotImplementedError)
+    }
+}
+
+impl BackendWindow for Window {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+    fn set_timeout(&mut self, _: Option<Duration>) {
+    }
+    fn add_menu_entry(&self, _: u32, _: Option<u32>, _: &str, _: MenuItemKind) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn add_menu_separator(&self, _: u32, _: Option<u32>) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn remove_menu_entry(&self, _: u32) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn set_menu_item_enabled(&self, _: u32, _: bool) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn set_icon_from_file(&self, _: &str) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn set_icon_from_resource(&self, _: &str) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn icon_name(&self) -> Result<Option<String>, SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn set_tooltip(&self, _: &str) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn set_label(&self, _: &str, _: &str) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn notify(&self, _: &str, _: &str) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn set_attention(&self, _: bool) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn set_attention_icon(&self, _: &IconResource) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn delete_icon(&self) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn add_timer(&self, _: u32, _: Duration, _: TimerTick) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    fn remove_timer(&self, _: u32) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+    // new() never hands out a window, so there is nothing to shut down.
+    fn quit(&mut self) {
     }
 }
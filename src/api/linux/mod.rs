@@ -1,25 +1,242 @@
 use gtk::{ self, Window as GTKWindow, WindowType, WidgetExt,
-           Inhibit, Widget, Menu, MenuShellExt, MenuItemExt };
-use libappindicator::{AppIndicator,
-                      AppIndicatorStatus};
-use std::cell::{RefCell};
+           Inhibit, Widget, Menu, MenuShellExt, MenuItemExt, ContainerExt, CheckMenuItemExt };
+use gtk_sys;
+use gobject_sys;
+use libappindicator_sys::{self, AppIndicator, AppIndicatorCategory,
+                          AppIndicatorStatus};
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
-use {SystrayEvent, SystrayError};
+use std::os::raw::c_char;
+use std::rc::Rc;
+use {SystrayEvent, SystrayError, Capabilities, IconResource, IndicatorCategory, MenuItemKind, TrayConfig};
+use api::{interval_ms, BackendWindow, TimerTick};
 use glib;
+use glib::Cast;
+use glib::translate::{from_glib_none, ToGlibPtr};
+use glib_sys;
+use gio_sys;
 use std;
+use std::path::Path;
 use std::thread;
-use std::sync::mpsc::{channel, Sender};
+use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 
 // Gtk specific struct that will live only in the Gtk thread, since a lot of the
-// base types involved don't implement Send (for good reason).
+// base types involved don't implement Send (for good reason). That is the
+// shared gtk thread, or the caller's with Window::attach.
 pub struct GtkSystrayApp {
     menu: gtk::Menu,
-    ai: RefCell<AppIndicator>,
+    ai: *mut AppIndicator,
     menu_items: RefCell<HashMap<u32, gtk::MenuItem>>,
-    event_tx: Sender<SystrayEvent>
+    separators: RefCell<HashMap<u32, gtk::SeparatorMenuItem>>,
+    // Check and radio items once more, for their marks.
+    checks: RefCell<HashMap<u32, gtk::CheckMenuItem>>,
+    radios: RefCell<HashMap<u32, gtk::RadioMenuItem>>,
+    // The menu of each submenu item.
+    submenus: RefCell<HashMap<u32, gtk::Menu>>,
+    // The submenu every entry sits in, None for the indicator's menu.
+    parents: RefCell<HashMap<u32, Option<u32>>>,
+    // Set while we move marks ourselves, which activates items just like a
+    // click does.
+    updating: Rc<Cell<bool>>,
+    // Whether the icon is up and asks for attention, which together make
+    // the indicator's status.
+    shown: Cell<bool>,
+    attention: Cell<bool>,
+    // Whether the tray gave an attention icon. Without one the indicator
+    // would show none while asking for attention.
+    attention_icon: Cell<bool>,
+    title: String,
+    watch_id: u32,
+    // Sources of running timers. A timer that stops itself takes its entry
+    // out, so we never remove a source glib already dropped.
+    timers: Arc<Mutex<HashMap<u32, glib::SourceId>>>,
+    event_tx: EventTx
+}
+
+// Shared with Window, so quitting closes the event channel right away from
+// whatever thread it happens on.
+type EventTx = Arc<Mutex<Option<Sender<SystrayEvent>>>>;
+
+fn send_event(event_tx: &EventTx, event: SystrayEvent) {
+    if let Some(ref tx) = *event_tx.lock().unwrap_or_else(|e| e.into_inner()) {
+        tx.send(event).ok();
+    }
+}
+
+// The same file under another name, dir/./name for dir/name.
+fn respelled(file: &str) -> String {
+    let path = Path::new(file);
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => dir.join(".").join(name).to_string_lossy().into_owned(),
+        _ => file.to_string()
+    }
+}
+
+struct HostWatch {
+    // Set once the watch has told us whether the watcher was there to begin
+    // with, which is its first callback either way.
+    started: Cell<bool>,
+    event_tx: EventTx
+}
+
+// Work queued by Window for the gtk thread. Each request carries its own
+// reply channel, so callers get the real outcome back.
+type Request = Box<dyn FnOnce(&mut Apps) + Send>;
+
+// Every app living on the shared gtk thread, by Window.
+type Apps = HashMap<usize, GtkSystrayApp>;
+
+static NEXT_APP_ID: AtomicUsize = AtomicUsize::new(0);
+
+// Every StatusNotifierItem host registers items through this name, so a new
+// owner means the host restarted.
+const SNI_WATCHER: &'static str = "org.kde.StatusNotifierWatcher";
+
+// Only used by the shared gtk thread, attached windows keep the app themselves.
+thread_local!(static GTK_STASH: RefCell<Option<(Apps, Receiver<Request>)>> = RefCell::new(None));
+
+// Gtk can only ever be initialised on one thread, and gtk-rs won't even try
+// another one later, so all windows share this one for the life of the
+// process. gtk::main() only runs while there are windows on it though: the
+// last one to quit stops the loop and waits for it to return, and the next
+// new window starts it again on the same thread.
+struct GtkThread {
+    requests: Sender<Request>,
+    run: Sender<()>,
+    stopped: Receiver<()>,
+    windows: usize,
+}
+
+lazy_static! {
+    static ref GTK_THREAD: Mutex<Option<GtkThread>> = Mutex::new(None);
+}
+
+fn start_gtk_thread() -> Result<GtkThread, SystrayError> {
+    let (tx, rx) = channel();
+    let (requests_tx, requests_rx) = channel();
+    let (run_tx, run_rx) = channel();
+    let (stopped_tx, stopped_rx) = channel();
+    thread::spawn(move || {
+        if let Err(_) = gtk::init() {
+            tx.send(Err(SystrayError::OsError(format!("{}", "Gtk init error!")))).ok();
+            return;
+        }
+        GTK_STASH.with(|stash| {
+            (*stash.borrow_mut()) = Some((HashMap::new(), requests_rx));
+        });
+        tx.send(Ok(())).ok();
+        for () in run_rx.iter() {
+            gtk::main();
+            stopped_tx.send(()).ok();
+        }
+    });
+    match rx.recv() {
+        Ok(Ok(())) => Ok(GtkThread {
+            requests: requests_tx,
+            run: run_tx,
+            stopped: stopped_rx,
+            windows: 0
+        }),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(SystrayError::BackendGone)
+    }
 }
 
-thread_local!(static GTK_STASH: RefCell<Option<GtkSystrayApp>> = RefCell::new(None));
+// Puts a new window on the gtk thread, starting the thread or its loop if
+// needed. Returns the queue for its requests.
+fn join_gtk_thread() -> Result<Sender<Request>, SystrayError> {
+    let mut gtk_thread = GTK_THREAD.lock().unwrap_or_else(|e| e.into_inner());
+    if gtk_thread.is_none() {
+        *gtk_thread = Some(start_gtk_thread()?);
+    }
+    let gtk_thread = gtk_thread.as_mut().unwrap();
+    if gtk_thread.windows == 0 {
+        gtk_thread.run.send(()).map_err(|_| SystrayError::BackendGone)?;
+    }
+    gtk_thread.windows += 1;
+    Ok(gtk_thread.requests.clone())
+}
+
+// Takes a window off the gtk thread. Without windows left gtk::main() is
+// stopped, and this only returns once it has.
+fn leave_gtk_thread() {
+    let mut gtk_thread = GTK_THREAD.lock().unwrap_or_else(|e| e.into_inner());
+    let gtk_thread = match *gtk_thread {
+        Some(ref mut gtk_thread) => gtk_thread,
+        None => return
+    };
+    gtk_thread.windows -= 1;
+    if gtk_thread.windows > 0 {
+        return;
+    }
+    let quit = call_gtk_thread(&gtk_thread.requests, None, |_: &mut Apps| {
+        gtk::main_quit();
+        Ok(())
+    });
+    if quit.is_ok() {
+        gtk_thread.stopped.recv().ok();
+    }
+}
+
+// Where a queued request is at. A request that timed out is cancelled, so
+// it can't change the tray behind the back of a caller told it failed.
+const PENDING: usize = 0;
+const RUNNING: usize = 1;
+const CANCELLED: usize = 2;
+
+// Queue f for the gtk thread and wait for its result, up to the timeout if
+// one is set. Requests the gtk thread got to before the timeout are waited
+// for, so they never look failed when they did happen.
+fn call_gtk_thread<F>(requests: &Sender<Request>,
+                      timeout: Option<Duration>,
+                      f: F) -> Result<(), SystrayError>
+    where F: FnOnce(&mut Apps) -> Result<(), SystrayError> + Send + 'static {
+    let (tx, rx) = channel();
+    let state = Arc::new(AtomicUsize::new(PENDING));
+    let request_state = state.clone();
+    let request: Request = Box::new(move |apps: &mut Apps| {
+        if request_state.compare_exchange(PENDING, RUNNING, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+            tx.send(f(apps)).ok();
+        }
+    });
+    // The receiving end lives in the gtk thread's stash, so a failed send
+    // means that thread is gone.
+    if requests.send(request).is_err() {
+        return Err(SystrayError::BackendGone);
+    }
+    // Note this is glib, not gtk. Calling gtk::idle_add will panic us due to
+    // being on different threads. glib::idle_add can run across threads.
+    glib::idle_add(|| {
+        run_requests();
+        gtk::Continue(false)
+    });
+    wait_for_reply(&rx, &state, timeout)
+}
+
+// Waits for a request that only runs if it moves state from PENDING to
+// RUNNING first. Giving up moves it to CANCELLED, unless it started already.
+fn wait_for_reply(rx: &Receiver<Result<(), SystrayError>>,
+                  state: &AtomicUsize,
+                  timeout: Option<Duration>) -> Result<(), SystrayError> {
+    let reply = match timeout {
+        Some(t) => match rx.recv_timeout(t) {
+            Ok(reply) => Ok(reply),
+            Err(RecvTimeoutError::Timeout) => {
+                if state.compare_exchange(PENDING, CANCELLED, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                    Err(SystrayError::Timeout)
+                } else {
+                    rx.recv().map_err(|_| SystrayError::BackendGone)
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(SystrayError::BackendGone),
+        },
+        None => rx.recv().map_err(|_| SystrayError::BackendGone)
+    };
+    reply?
+}
 
 pub struct MenuItemInfo {
     mid: u32,
@@ -29,153 +246,703 @@ pub struct MenuItemInfo {
     checked: bool
 }
 
-type Callback = Box<(Fn(&GtkSystrayApp) -> () + 'static)>;
+type Callback = Box<dyn Fn(&GtkSystrayApp) -> () + 'static>;
 
-// Convenience function to clean up thread local unwrapping
-fn run_on_gtk_thread<F>(f: F)
-    where F: std::ops::Fn(&GtkSystrayApp) -> () + Send + 'static {
-    // Note this is glib, not gtk. Calling gtk::idle_add will panic us due to
-    // being on different threads. glib::idle_add can run across threads.
-    glib::idle_add(move || {
-        GTK_STASH.with(|stash| {
-            let stash = stash.borrow();
-            let stash = stash.as_ref();
-            if let Some(stash) = stash {
-                f(stash);
+// Runs whatever windows queued for the shared gtk thread. Only call this on
+// the gtk thread.
+fn run_requests() {
+    GTK_STASH.with(|stash| {
+        if let Some((ref mut apps, ref requests)) = *stash.borrow_mut() {
+            while let Ok(request) = requests.try_recv() {
+                request(apps);
             }
-        });
-        gtk::Continue(false)
+        }
     });
 }
 
+unsafe extern "C" fn watcher_appeared(_: *mut gio_sys::GDBusConnection,
+                                      _: *const c_char,
+                                      _: *const c_char,
+                                      user_data: glib_sys::gpointer) {
+    let watch = &*(user_data as *const HostWatch);
+    // Appearing right away is the host we registered with at startup. Any
+    // later appearance is a host that came up, or came back, without our
+    // icon, including one that started after us.
+    if watch.started.replace(true) {
+        send_event(&watch.event_tx, SystrayEvent::HostChanged);
+    }
+}
+
+unsafe extern "C" fn watcher_vanished(_: *mut gio_sys::GDBusConnection,
+                                      _: *const c_char,
+                                      user_data: glib_sys::gpointer) {
+    let watch = &*(user_data as *const HostWatch);
+    watch.started.set(true);
+}
+
+unsafe extern "C" fn free_host_watch(user_data: glib_sys::gpointer) {
+    drop(Box::from_raw(user_data as *mut HostWatch));
+}
+
+fn indicator_category(category: IndicatorCategory) -> AppIndicatorCategory {
+    match category {
+        IndicatorCategory::ApplicationStatus => AppIndicatorCategory::APP_INDICATOR_CATEGORY_APPLICATION_STATUS,
+        IndicatorCategory::Communications => AppIndicatorCategory::APP_INDICATOR_CATEGORY_COMMUNICATIONS,
+        IndicatorCategory::SystemServices => AppIndicatorCategory::APP_INDICATOR_CATEGORY_SYSTEM_SERVICES,
+        IndicatorCategory::Hardware => AppIndicatorCategory::APP_INDICATOR_CATEGORY_HARDWARE,
+    }
+}
+
 impl GtkSystrayApp {
-    pub fn new(event_tx: Sender<SystrayEvent>) -> Result<GtkSystrayApp, SystrayError> {
-        if let Err(e) = gtk::init() {
+    pub fn new(event_tx: EventTx, config: &TrayConfig) -> Result<GtkSystrayApp, SystrayError> {
+        if let Err(_) = gtk::init() {
             return Err(SystrayError::OsError(format!("{}", "Gtk init error!")));
         }
-        let mut m = gtk::Menu::new();
-        let mut ai = AppIndicator::new("", "");
-        ai.set_status(AppIndicatorStatus::APP_INDICATOR_STATUS_ACTIVE);
-        ai.set_menu(&mut m);
+        let m = gtk::Menu::new();
+        let icon_name = match config.icon {
+            Some(IconResource::File(ref name)) | Some(IconResource::Resource(ref name)) => name.clone(),
+            None => "".to_string(),
+        };
+        let ai = unsafe {
+            let ai = libappindicator_sys::app_indicator_new(
+                config.id.to_glib_none().0,
+                icon_name.to_glib_none().0,
+                indicator_category(config.category));
+            libappindicator_sys::app_indicator_set_title(ai, config.title.to_glib_none().0);
+            let menu: *mut gtk_sys::GtkMenu = m.to_glib_none().0;
+            libappindicator_sys::app_indicator_set_menu(ai, menu);
+            // Stays hidden until there is an icon to show.
+            libappindicator_sys::app_indicator_set_status(
+                ai, AppIndicatorStatus::APP_INDICATOR_STATUS_PASSIVE);
+            ai
+        };
+        let watch = Box::new(HostWatch {
+            started: Cell::new(false),
+            event_tx: event_tx.clone()
+        });
+        let watch_id = unsafe {
+            gio_sys::g_bus_watch_name(gio_sys::GBusType::Session,
+                                      SNI_WATCHER.to_glib_none().0,
+                                      gio_sys::G_BUS_NAME_WATCHER_FLAGS_NONE,
+                                      Some(watcher_appeared),
+                                      Some(watcher_vanished),
+                                      Box::into_raw(watch) as glib_sys::gpointer,
+                                      Some(free_host_watch))
+        };
         Ok(GtkSystrayApp {
             menu: m,
-            ai: RefCell::new(ai),
+            ai: ai,
             menu_items: RefCell::new(HashMap::new()),
+            separators: RefCell::new(HashMap::new()),
+            checks: RefCell::new(HashMap::new()),
+            radios: RefCell::new(HashMap::new()),
+            submenus: RefCell::new(HashMap::new()),
+            parents: RefCell::new(HashMap::new()),
+            updating: Rc::new(Cell::new(false)),
+            shown: Cell::new(false),
+            attention: Cell::new(false),
+            attention_icon: Cell::new(false),
+            title: config.title.clone(),
+            watch_id: watch_id,
+            timers: Arc::new(Mutex::new(HashMap::new())),
             event_tx: event_tx
         })
     }
 
-    pub fn systray_menu_selected(&self, menu_id: u32) {
-        self.event_tx.send(SystrayEvent {
-            menu_index: menu_id as u32,
-        }).ok();
+    fn set_status(&self, status: AppIndicatorStatus) {
+        unsafe {
+            libappindicator_sys::app_indicator_set_status(self.ai, status);
+        }
+    }
+
+    fn show_status(&self) {
+        self.shown.set(true);
+        self.set_status(if self.attention.get() {
+            AppIndicatorStatus::APP_INDICATOR_STATUS_ATTENTION
+        } else {
+            AppIndicatorStatus::APP_INDICATOR_STATUS_ACTIVE
+        });
     }
 
-    pub fn add_menu_separator(&self, item_idx: u32) {
-        //let mut menu_items = self.menu_items.borrow_mut();
+    fn menu_for(&self, parent: Option<u32>) -> Result<gtk::Menu, SystrayError> {
+        match parent {
+            Some(parent) => self.submenus.borrow().get(&parent).cloned()
+                .ok_or(SystrayError::UnknownMenuItem(parent)),
+            None => Ok(self.menu.clone()),
+        }
+    }
+
+    pub fn add_menu_separator(&self, item_idx: u32, parent: Option<u32>) -> Result<(), SystrayError> {
+        if self.separators.borrow().contains_key(&item_idx) {
+            return Ok(());
+        }
+        let menu = self.menu_for(parent)?;
         let m = gtk::SeparatorMenuItem::new();
-        self.menu.append(&m);
-        //menu_items.insert(item_idx, m);
-        self.menu.show_all();
+        menu.append(&m);
+        self.separators.borrow_mut().insert(item_idx, m);
+        self.parents.borrow_mut().insert(item_idx, parent);
+        menu.show_all();
+        Ok(())
+    }
+
+    // Moving a mark activates the item, and the one losing it in a radio
+    // group, which must not look like clicks.
+    fn set_checked(&self, item: &gtk::CheckMenuItem, checked: bool) {
+        self.updating.set(true);
+        item.set_active(checked);
+        self.updating.set(false);
     }
 
-    pub fn add_menu_entry(&self, item_idx: u32, item_name: &String) {
-        let mut menu_items = self.menu_items.borrow_mut();
-        if menu_items.contains_key(&item_idx) {
-            let m : &gtk::MenuItem = menu_items.get(&item_idx).unwrap();
+    // Radio items join the group of the other radio items in their menu.
+    // Gtk keeps one of a group checked at all times, so the first one shows
+    // its mark until another takes it.
+    pub fn add_menu_entry(&self, item_idx: u32, parent: Option<u32>, item_name: &str,
+                          kind: MenuItemKind) -> Result<(), SystrayError> {
+        let checked = match kind {
+            MenuItemKind::Check(checked) | MenuItemKind::Radio(checked) => Some(checked),
+            MenuItemKind::Plain | MenuItemKind::Submenu => None,
+        };
+        if let Some(m) = self.menu_items.borrow().get(&item_idx) {
             m.set_label(item_name);
+            if let (Some(checked), Some(c)) = (checked, self.checks.borrow().get(&item_idx)) {
+                self.set_checked(c, checked);
+            }
             self.menu.show_all();
-            return;
+            return Ok(());
+        }
+        let menu = self.menu_for(parent)?;
+        let m: gtk::MenuItem = match kind {
+            MenuItemKind::Plain => gtk::MenuItem::new_with_label(item_name),
+            MenuItemKind::Check(_) => {
+                let c = gtk::CheckMenuItem::new_with_label(item_name);
+                self.checks.borrow_mut().insert(item_idx, c.clone());
+                c.upcast()
+            }
+            MenuItemKind::Radio(_) => {
+                let r = {
+                    let parents = self.parents.borrow();
+                    let radios = self.radios.borrow();
+                    let group = radios.iter()
+                        .find(|&(idx, _)| parents.get(idx) == Some(&parent))
+                        .map(|(_, r)| r);
+                    gtk::RadioMenuItem::new_with_label_from_widget(group, Some(item_name))
+                };
+                self.radios.borrow_mut().insert(item_idx, r.clone());
+                self.checks.borrow_mut().insert(item_idx, r.clone().upcast());
+                r.upcast()
+            }
+            MenuItemKind::Submenu => {
+                let m = gtk::MenuItem::new_with_label(item_name);
+                let submenu = gtk::Menu::new();
+                m.set_submenu(Some(&submenu));
+                self.submenus.borrow_mut().insert(item_idx, submenu);
+                m
+            }
+        };
+        menu.append(&m);
+        if let (Some(checked), Some(c)) = (checked, self.checks.borrow().get(&item_idx)) {
+            self.set_checked(c, checked);
         }
-        let m = gtk::MenuItem::new_with_label(item_name);
-        self.menu.append(&m);
-        m.connect_activate(move |_| {
-            run_on_gtk_thread(move |stash : &GtkSystrayApp| {
-                stash.systray_menu_selected(item_idx);
+        // Opening a submenu activates its item, that is no click either.
+        if kind != MenuItemKind::Submenu {
+            let event_tx = self.event_tx.clone();
+            let updating = self.updating.clone();
+            let radio = match kind {
+                MenuItemKind::Radio(_) => true,
+                _ => false,
+            };
+            m.connect_activate(move |m| {
+                if updating.get() {
+                    return;
+                }
+                // The radio item a click takes the mark from is activated
+                // too, only the one getting it counts.
+                if radio {
+                    if let Ok(c) = m.clone().downcast::<gtk::CheckMenuItem>() {
+                        if !c.get_active() {
+                            return;
+                        }
+                    }
+                }
+                send_event(&event_tx, SystrayEvent::MenuItemClick(item_idx));
             });
-        });
-        menu_items.insert(item_idx, m);
+        }
+        self.menu_items.borrow_mut().insert(item_idx, m);
+        self.parents.borrow_mut().insert(item_idx, parent);
+        menu.show_all();
         self.menu.show_all();
+        Ok(())
     }
 
+    pub fn remove_menu_entry(&self, item_idx: u32) -> Result<(), SystrayError> {
+        let parent = match self.parents.borrow_mut().remove(&item_idx) {
+            Some(parent) => parent,
+            None => return Err(SystrayError::UnknownMenuItem(item_idx)),
+        };
+        let menu = self.menu_for(parent)?;
+        if let Some(m) = self.menu_items.borrow_mut().remove(&item_idx) {
+            menu.remove(&m);
+        }
+        if let Some(m) = self.separators.borrow_mut().remove(&item_idx) {
+            menu.remove(&m);
+        }
+        self.checks.borrow_mut().remove(&item_idx);
+        self.submenus.borrow_mut().remove(&item_idx);
+        // Out of its group as well, where it would still count.
+        if let Some(r) = self.radios.borrow_mut().remove(&item_idx) {
+            r.set_group(&[]);
+        }
+        Ok(())
+    }
+
+    pub fn set_menu_item_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), SystrayError> {
+        match self.menu_items.borrow().get(&item_idx) {
+            Some(m) => {
+                m.set_sensitive(enabled);
+                Ok(())
+            }
+            None => Err(SystrayError::UnknownMenuItem(item_idx))
+        }
+    }
+
+    // AppIndicator ignores an icon it already has, and hosts keep what they
+    // loaded from a path, so the same file again, say after it changed, goes
+    // up under another spelling of its path.
     pub fn set_icon_from_file(&self, file: &String) {
-        let mut ai = self.ai.borrow_mut();
-        ai.set_icon_full(file, "icon");
+        let name = match self.icon_name() {
+            Some(ref shown) if shown == file => respelled(file),
+            _ => file.clone()
+        };
+        self.set_icon(&name);
+    }
+
+    // Resources are icon theme names here, e.g. "mail-unread". The same one
+    // again is set after a blank one, so the host looks it up anew.
+    pub fn set_icon_from_resource(&self, name: &str) {
+        if self.icon_name().as_ref().map(|shown| &shown[..]) == Some(name) {
+            self.set_icon("");
+        }
+        self.set_icon(name);
+    }
+
+    // The icon as the indicator has it.
+    pub fn icon_name(&self) -> Option<String> {
+        unsafe {
+            from_glib_none(libappindicator_sys::app_indicator_get_icon(self.ai))
+        }
+    }
+
+    fn set_icon(&self, name: &str) {
+        unsafe {
+            libappindicator_sys::app_indicator_set_icon_full(self.ai,
+                                                             name.to_glib_none().0,
+                                                             "icon".to_glib_none().0);
+        }
+        if !self.attention_icon.get() {
+            self.set_attention_icon_name(name);
+        }
+        self.show_status();
+    }
+
+    // File names go in as they are, like for the icon itself.
+    pub fn set_attention_icon(&self, name: &str) {
+        self.attention_icon.set(true);
+        self.set_attention_icon_name(name);
+    }
+
+    fn set_attention_icon_name(&self, name: &str) {
+        unsafe {
+            libappindicator_sys::app_indicator_set_attention_icon_full(self.ai,
+                                                                       name.to_glib_none().0,
+                                                                       "attention".to_glib_none().0);
+        }
+    }
+
+    // A hidden icon stays hidden, it asks for attention once shown.
+    pub fn set_attention(&self, attention: bool) {
+        self.attention.set(attention);
+        if self.shown.get() {
+            self.show_status();
+        }
+    }
+
+    // AppIndicator has no tooltips, the title is what StatusNotifierItem
+    // hosts fall back to showing instead. No tooltip means the real title.
+    pub fn set_tooltip(&self, tooltip: &str) {
+        let title = if tooltip.is_empty() { &self.title[..] } else { tooltip };
+        unsafe {
+            libappindicator_sys::app_indicator_set_title(self.ai, title.to_glib_none().0);
+        }
+    }
+
+    pub fn set_label(&self, label: &str, guide: &str) {
+        unsafe {
+            libappindicator_sys::app_indicator_set_label(self.ai,
+                                                         label.to_glib_none().0,
+                                                         guide.to_glib_none().0);
+        }
+    }
+
+    pub fn delete_icon(&self) {
+        self.shown.set(false);
+        self.set_status(AppIndicatorStatus::APP_INDICATOR_STATUS_PASSIVE);
+    }
+
+    pub fn add_timer(&self, timer_id: u32, ms: u32, mut tick: TimerTick) {
+        let timers = self.timers.clone();
+        let mut all = self.timers.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(old) = all.remove(&timer_id) {
+            glib::source_remove(old);
+        }
+        let source = glib::timeout_add(ms, move || {
+            let keep = tick();
+            if !keep {
+                timers.lock().unwrap_or_else(|e| e.into_inner()).remove(&timer_id);
+            }
+            glib::Continue(keep)
+        });
+        all.insert(timer_id, source);
+    }
+
+    pub fn remove_timer(&self, timer_id: u32) {
+        let source = self.timers.lock().unwrap_or_else(|e| e.into_inner()).remove(&timer_id);
+        if let Some(source) = source {
+            glib::source_remove(source);
+        }
     }
 }
 
-pub struct Window {
-    gtk_loop: Option<thread::JoinHandle<()>>
+impl Drop for GtkSystrayApp {
+    fn drop(&mut self) {
+        let timers: Vec<_> = self.timers.lock().unwrap_or_else(|e| e.into_inner())
+            .drain().map(|(_, source)| source).collect();
+        for source in timers {
+            glib::source_remove(source);
+        }
+        // Dropping the last reference unregisters us from the host.
+        unsafe {
+            gio_sys::g_bus_unwatch_name(self.watch_id);
+            gobject_sys::g_object_unref(self.ai as *mut gobject_sys::GObject);
+        }
+        self.event_tx.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
 }
 
-impl Window {
-    pub fn new(event_tx: Sender<SystrayEvent>) -> Result<Window, SystrayError> {
+// Lets the app of an attached window ride along in closures for its main
+// context. They only run, and dereference it, on the owner thread.
+struct AppPtr(*mut GtkSystrayApp);
+
+unsafe impl Send for AppPtr {}
+
+// The app of a window that attached to the caller's main context. Only the
+// owner thread touches it, others hand their work over to that context.
+struct Attached {
+    app: Option<Box<GtkSystrayApp>>,
+    // Cleared on the owner thread when app goes away, handed over work
+    // checks it there before touching app.
+    alive: Arc<AtomicBool>,
+    owner: thread::ThreadId,
+    event_tx: EventTx
+}
+
+unsafe impl Send for Attached {}
+
+impl Attached {
+    // From other threads this waits for the owner's loop to run f, up to
+    // timeout, see Backend::GlibMainContext.
+    fn call<F>(&self, timeout: Option<Duration>, f: F) -> Result<(), SystrayError>
+        where F: FnOnce(&GtkSystrayApp) -> Result<(), SystrayError> + Send + 'static {
+        let app = match self.app {
+            Some(ref app) => app,
+            None => return Err(SystrayError::BackendGone)
+        };
+        if thread::current().id() == self.owner {
+            return f(app);
+        }
+        let app = AppPtr(&**app as *const GtkSystrayApp as *mut GtkSystrayApp);
+        let alive = self.alive.clone();
         let (tx, rx) = channel();
-        let gtk_loop = thread::spawn(move || {
-            GTK_STASH.with(|stash| {
-                match GtkSystrayApp::new(event_tx) {
-                    Ok(data) => {
-                        (*stash.borrow_mut()) = Some(data);
-                        tx.send(Ok(()));
-                    }
-                    Err(e) => {
-                        tx.send(Err(e));
-                        return;
-                    }
+        let state = Arc::new(AtomicUsize::new(PENDING));
+        let request_state = state.clone();
+        let mut f = Some(f);
+        // Dropped unrun along with the context, which drops tx and so
+        // reports the backend gone.
+        glib::idle_add(move || {
+            if let Some(f) = f.take() {
+                if request_state.compare_exchange(PENDING, RUNNING, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                    let reply = if alive.load(Ordering::SeqCst) {
+                        f(unsafe { &*app.0 })
+                    } else {
+                        Err(SystrayError::BackendGone)
+                    };
+                    tx.send(reply).ok();
                 }
-            });
-            gtk::main();
+            }
+            glib::Continue(false)
+        });
+        wait_for_reply(&rx, &state, timeout)
+    }
+
+    fn quit(&mut self) {
+        // Nothing is sent after this, which ends the core's event pump no
+        // matter when the gtk side gets torn down.
+        self.event_tx.lock().unwrap_or_else(|e| e.into_inner()).take();
+        let app = match self.app.take() {
+            Some(app) => app,
+            None => return
+        };
+        if thread::current().id() == self.owner {
+            self.alive.store(false, Ordering::SeqCst);
+            drop(app);
+            return;
+        }
+        let app = AppPtr(Box::into_raw(app));
+        let alive = self.alive.clone();
+        glib::idle_add(move || {
+            if alive.swap(false, Ordering::SeqCst) {
+                drop(unsafe { Box::from_raw(app.0) });
+            }
+            glib::Continue(false)
+        });
+    }
+}
+
+impl Drop for Attached {
+    fn drop(&mut self) {
+        self.quit();
+    }
+}
+
+enum Mode {
+    // An app on the shared gtk thread, reached through its request queue,
+    // and the sender it sends its events with.
+    Thread(usize, Sender<Request>, EventTx),
+    Attached(Attached),
+    // After quit.
+    Gone
+}
+
+pub struct Window {
+    mode: Mode,
+    timeout: Option<Duration>
+}
+
+impl Window {
+    pub fn new(event_tx: Sender<SystrayEvent>, config: &TrayConfig) -> Result<Window, SystrayError> {
+        let requests = join_gtk_thread()?;
+        let id = NEXT_APP_ID.fetch_add(1, Ordering::SeqCst);
+        let event_tx = Arc::new(Mutex::new(Some(event_tx)));
+        let config = config.clone();
+        let app_event_tx = event_tx.clone();
+        let created = call_gtk_thread(&requests, None, move |apps: &mut Apps| {
+            let app = GtkSystrayApp::new(app_event_tx, &config)?;
+            apps.insert(id, app);
+            Ok(())
         });
-        match rx.recv().unwrap() {
-            Ok(()) => Ok(Window {
-                gtk_loop: Some(gtk_loop)
+        if let Err(e) = created {
+            leave_gtk_thread();
+            return Err(e);
+        }
+        Ok(Window {
+            mode: Mode::Thread(id, requests, event_tx),
+            timeout: None
+        })
+    }
+
+    // Uses the caller's default main context instead of a thread and
+    // gtk::main() of our own. Has to be called on the thread running that
+    // context, which should also be the one to drop the application. Calls
+    // from there run directly, calls from other threads are handed to the
+    // context and wait for it, see Backend::GlibMainContext.
+    pub fn attach(event_tx: Sender<SystrayEvent>, config: &TrayConfig) -> Result<Window, SystrayError> {
+        let event_tx = Arc::new(Mutex::new(Some(event_tx)));
+        let app = GtkSystrayApp::new(event_tx.clone(), config)?;
+        Ok(Window {
+            mode: Mode::Attached(Attached {
+                app: Some(Box::new(app)),
+                alive: Arc::new(AtomicBool::new(true)),
+                owner: thread::current().id(),
+                event_tx: event_tx
             }),
-            Err(e) => {
-                Err(e)
+            timeout: None
+        })
+    }
+
+    fn call<F>(&self, f: F) -> Result<(), SystrayError>
+        where F: FnOnce(&GtkSystrayApp) -> Result<(), SystrayError> + Send + 'static {
+        match self.mode {
+            Mode::Thread(id, ref requests, _) => {
+                call_gtk_thread(requests, self.timeout, move |apps: &mut Apps| {
+                    match apps.get(&id) {
+                        Some(app) => f(app),
+                        None => Err(SystrayError::BackendGone)
+                    }
+                })
             }
+            Mode::Attached(ref attached) => attached.call(self.timeout, f),
+            Mode::Gone => Err(SystrayError::BackendGone)
         }
     }
 
-    pub fn add_menu_entry(&self, item_idx: u32, item_name: &String) -> Result<(), SystrayError> {
-        let n = item_name.clone();
-        run_on_gtk_thread(move |stash : &GtkSystrayApp| {
-            stash.add_menu_entry(item_idx, &n);
-        });
+    pub fn shutdown(&self) -> Result<(), SystrayError> {
         Ok(())
     }
+}
 
-    pub fn add_menu_separator(&self, item_idx: u32) -> Result<(), SystrayError> {
-        run_on_gtk_thread(move |stash : &GtkSystrayApp| {
-            stash.add_menu_separator(item_idx);
-        });
-        Ok(())
+impl BackendWindow for Window {
+    fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
     }
 
-    pub fn set_icon_from_file(&self, file: &String) -> Result<(), SystrayError> {
-        let n : String = file.clone();
-        run_on_gtk_thread(move |stash : &GtkSystrayApp| {
+    fn capabilities(&self) -> Capabilities {
+        // AppIndicator never reports clicks on the icon itself.
+        Capabilities {
+            tooltip: true,
+            submenus: true,
+            check_items: true,
+            hide_show: true,
+            label: true,
+            attention: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn add_menu_entry(&self, item_idx: u32, parent: Option<u32>, item_name: &str,
+                      kind: MenuItemKind) -> Result<(), SystrayError> {
+        let n = item_name.to_string();
+        self.call(move |stash : &GtkSystrayApp| stash.add_menu_entry(item_idx, parent, &n, kind))
+    }
+
+    fn add_menu_separator(&self, item_idx: u32, parent: Option<u32>) -> Result<(), SystrayError> {
+        self.call(move |stash : &GtkSystrayApp| stash.add_menu_separator(item_idx, parent))
+    }
+
+    fn remove_menu_entry(&self, item_idx: u32) -> Result<(), SystrayError> {
+        self.call(move |stash : &GtkSystrayApp| stash.remove_menu_entry(item_idx))
+    }
+
+    fn set_menu_item_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), SystrayError> {
+        self.call(move |stash : &GtkSystrayApp| stash.set_menu_item_enabled(item_idx, enabled))
+    }
+
+    fn set_icon_from_file(&self, file: &str) -> Result<(), SystrayError> {
+        // AppIndicator takes anything and quietly shows a blank icon, so at
+        // least catch the missing file. Here rather than on the gtk side, so
+        // attached windows report it to callers on other threads too.
+        if !Path::new(file).is_file() {
+            return Err(SystrayError::OsError(format!("No icon file at {}", file)));
+        }
+        let n : String = file.to_string();
+        self.call(move |stash : &GtkSystrayApp| {
             stash.set_icon_from_file(&n);
-        });
-        Ok(())
+            Ok(())
+        })
+    }
+
+    fn set_icon_from_resource(&self, resource: &str) -> Result<(), SystrayError> {
+        let n = resource.to_string();
+        self.call(move |stash : &GtkSystrayApp| {
+            stash.set_icon_from_resource(&n);
+            Ok(())
+        })
     }
 
-    pub fn set_icon_from_resource(&self, resource: &str) -> Result<(), SystrayError> {
-        panic!("Not implemented on this platform!");
+    fn icon_name(&self) -> Result<Option<String>, SystrayError> {
+        let (tx, rx) = channel();
+        self.call(move |stash : &GtkSystrayApp| {
+            tx.send(stash.icon_name()).ok();
+            Ok(())
+        })?;
+        rx.recv().map_err(|_| SystrayError::BackendGone)
     }
 
-    pub fn shutdown(&self) -> Result<(), SystrayError> {
-        Ok(())
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), SystrayError> {
+        let n = tooltip.to_string();
+        self.call(move |stash : &GtkSystrayApp| {
+            stash.set_tooltip(&n);
+            Ok(())
+        })
     }
 
-    pub fn set_tooltip(&self, tooltip: &str) -> Result<(), SystrayError> {
-        panic!("Not implemented on this platform!");
+    fn set_label(&self, label: &str, guide: &str) -> Result<(), SystrayError> {
+        let l = label.to_string();
+        let g = guide.to_string();
+        self.call(move |stash : &GtkSystrayApp| {
+            stash.set_label(&l, &g);
+            Ok(())
+        })
     }
 
-    pub fn quit(&self) {
-        glib::idle_add(|| {
-            gtk::main_quit();
-            glib::Continue(false)
-        });
+    // AppIndicator has nothing for this, notifications are a separate
+    // freedesktop service.
+    fn notify(&self, _: &str, _: &str) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+
+    // Until it is given an attention icon the indicator asks for attention
+    // with its usual one.
+    fn set_attention(&self, attention: bool) -> Result<(), SystrayError> {
+        self.call(move |stash : &GtkSystrayApp| {
+            stash.set_attention(attention);
+            Ok(())
+        })
     }
 
+    fn set_attention_icon(&self, icon: &IconResource) -> Result<(), SystrayError> {
+        let name = match *icon {
+            IconResource::File(ref file) => {
+                if !Path::new(file).is_file() {
+                    return Err(SystrayError::OsError(format!("No icon file at {}", file)));
+                }
+                file.clone()
+            }
+            IconResource::Resource(ref name) => name.clone(),
+        };
+        self.call(move |stash : &GtkSystrayApp| {
+            stash.set_attention_icon(&name);
+            Ok(())
+        })
+    }
+
+    fn delete_icon(&self) -> Result<(), SystrayError> {
+        self.call(|stash : &GtkSystrayApp| {
+            stash.delete_icon();
+            Ok(())
+        })
+    }
+
+    fn add_timer(&self, timer_id: u32, interval: Duration, tick: TimerTick) -> Result<(), SystrayError> {
+        let ms = interval_ms(interval)?;
+        self.call(move |stash : &GtkSystrayApp| {
+            stash.add_timer(timer_id, ms, tick);
+            Ok(())
+        })
+    }
+
+    fn remove_timer(&self, timer_id: u32) -> Result<(), SystrayError> {
+        self.call(move |stash : &GtkSystrayApp| {
+            stash.remove_timer(timer_id);
+            Ok(())
+        })
+    }
+
+    fn quit(&mut self) {
+        match std::mem::replace(&mut self.mode, Mode::Gone) {
+            Mode::Thread(id, requests, event_tx) => {
+                // As for Attached, nothing is sent after this, which ends the
+                // core's event pump whatever the gtk thread is up to. The app
+                // itself goes without a timeout, a cancelled removal would
+                // leave it in the tray. The gtk loop keeps going if other
+                // windows still need it.
+                event_tx.lock().unwrap_or_else(|e| e.into_inner()).take();
+                call_gtk_thread(&requests, None, move |apps: &mut Apps| {
+                    apps.remove(&id);
+                    Ok(())
+                }).ok();
+                leave_gtk_thread();
+            }
+            Mode::Attached(mut attached) => attached.quit(),
+            Mode::Gone => {}
+        }
+    }
 }
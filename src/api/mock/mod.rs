@@ -0,0 +1,514 @@
+// Backend that talks to no tray host at all and just remembers what it was
+// told. Tests keep a MockTray around to look at that state and to play the
+// part of the user or the host.
+
+use std::cmp;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use api::{interval_ms, BackendWindow, TimerTick};
+use {Capabilities, IconResource, IndicatorCategory, MenuItemKind, SystrayError, SystrayEvent, TrayConfig};
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum MockMenuEntry {
+    Item(u32, String),
+    // Label and whether it is checked.
+    Check(u32, String, bool),
+    Radio(u32, String, bool),
+    // Label and the entries of the submenu.
+    Submenu(u32, String, Vec<MockMenuEntry>),
+    Separator(u32),
+}
+
+impl MockMenuEntry {
+    fn idx(&self) -> u32 {
+        match *self {
+            MockMenuEntry::Item(idx, _) |
+            MockMenuEntry::Check(idx, _, _) |
+            MockMenuEntry::Radio(idx, _, _) |
+            MockMenuEntry::Submenu(idx, _, _) |
+            MockMenuEntry::Separator(idx) => idx,
+        }
+    }
+}
+
+// The entries of a submenu, or of the tray menu for None, with their own
+// submenus filled in.
+fn nest_menu(menu: &[(Option<u32>, MockMenuEntry)], parent: Option<u32>) -> Vec<MockMenuEntry> {
+    menu.iter().filter(|&&(p, _)| p == parent).map(|&(_, ref entry)| match *entry {
+        MockMenuEntry::Submenu(idx, ref label, _) => MockMenuEntry::Submenu(idx, label.clone(), nest_menu(menu, Some(idx))),
+        ref entry => entry.clone(),
+    }).collect()
+}
+
+struct MockTimer {
+    id: u32,
+    due: Duration,
+    interval: Duration,
+    // Taken out while it runs, so the tick can call back into the tray.
+    tick: Option<TimerTick>,
+}
+
+struct MockState {
+    id: String,
+    title: String,
+    category: IndicatorCategory,
+    icon: Option<IconResource>,
+    visible: bool,
+    tooltip: String,
+    label: String,
+    label_guide: String,
+    // Every entry with the submenu it sits in, submenus themselves are kept
+    // empty here.
+    menu: Vec<(Option<u32>, MockMenuEntry)>,
+    disabled: HashSet<u32>,
+    notifications: Vec<(String, String)>,
+    attention: bool,
+    attention_icon: Option<IconResource>,
+    // Icons fail to load while set, see fail_icons.
+    failing_icons: bool,
+    capabilities: Capabilities,
+    event_tx: Option<Sender<SystrayEvent>>,
+    // Virtual time, only advance() moves it.
+    now: Duration,
+    timers: Vec<MockTimer>,
+}
+
+impl MockState {
+    fn menu_pos(&self, item_idx: u32) -> Option<usize> {
+        self.menu.iter().position(|&(_, ref entry)| match *entry {
+            MockMenuEntry::Separator(_) => false,
+            ref entry => entry.idx() == item_idx,
+        })
+    }
+
+    // Entries only go in submenus the host was told about.
+    fn check_parent(&self, parent: Option<u32>) -> Result<(), SystrayError> {
+        let parent = match parent {
+            Some(parent) => parent,
+            None => return Ok(()),
+        };
+        if !self.capabilities.submenus {
+            return Err(SystrayError::NotImplementedError);
+        }
+        match self.menu_pos(parent).map(|pos| &self.menu[pos].1) {
+            Some(&MockMenuEntry::Submenu(..)) => Ok(()),
+            _ => Err(SystrayError::UnknownMenuItem(parent)),
+        }
+    }
+
+    // Checks a radio item and clears the others of its menu.
+    fn check_radio(&mut self, pos: usize) {
+        let parent = self.menu[pos].0;
+        for (i, &mut (p, ref mut entry)) in self.menu.iter_mut().enumerate() {
+            if let MockMenuEntry::Radio(_, _, ref mut checked) = *entry {
+                if p == parent {
+                    *checked = i == pos;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MockTray {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockTray {
+    pub fn new() -> MockTray {
+        MockTray {
+            state: Arc::new(Mutex::new(MockState {
+                id: "".to_string(),
+                title: "".to_string(),
+                category: IndicatorCategory::ApplicationStatus,
+                icon: None,
+                visible: false,
+                tooltip: "".to_string(),
+                label: "".to_string(),
+                label_guide: "".to_string(),
+                menu: Vec::new(),
+                disabled: HashSet::new(),
+                notifications: Vec::new(),
+                attention: false,
+                attention_icon: None,
+                failing_icons: false,
+                capabilities: Capabilities {
+                    left_click: true,
+                    tooltip: true,
+                    icon_from_buffer: false,
+                    submenus: false,
+                    check_items: false,
+                    notifications: true,
+                    attention: false,
+                    hide_show: true,
+                    label: true,
+                },
+                event_tx: None,
+                now: Duration::from_secs(0),
+                timers: Vec::new(),
+            }))
+        }
+    }
+
+    fn state<'a>(&'a self) -> MutexGuard<'a, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn id(&self) -> String {
+        self.state().id.clone()
+    }
+
+    pub fn title(&self) -> String {
+        self.state().title.clone()
+    }
+
+    pub fn category(&self) -> IndicatorCategory {
+        self.state().category
+    }
+
+    pub fn icon(&self) -> Option<IconResource> {
+        self.state().icon.clone()
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.state().visible
+    }
+
+    pub fn tooltip(&self) -> String {
+        self.state().tooltip.clone()
+    }
+
+    pub fn label(&self) -> String {
+        self.state().label.clone()
+    }
+
+    pub fn label_guide(&self) -> String {
+        self.state().label_guide.clone()
+    }
+
+    pub fn menu(&self) -> Vec<MockMenuEntry> {
+        nest_menu(&self.state().menu, None)
+    }
+
+    pub fn is_enabled(&self, item_idx: u32) -> bool {
+        !self.state().disabled.contains(&item_idx)
+    }
+
+    // Title and body of every notification shown so far, oldest first.
+    pub fn notifications(&self) -> Vec<(String, String)> {
+        self.state().notifications.clone()
+    }
+
+    pub fn wants_attention(&self) -> bool {
+        self.state().attention
+    }
+
+    pub fn attention_icon(&self) -> Option<IconResource> {
+        self.state().attention_icon.clone()
+    }
+
+    // True between the application being built and it quitting.
+    pub fn is_running(&self) -> bool {
+        self.state().event_tx.is_some()
+    }
+
+    pub fn set_capabilities(&self, capabilities: Capabilities) {
+        self.state().capabilities = capabilities;
+    }
+
+    // Makes showing an icon fail, like a backend that can't load it, until
+    // called with false again.
+    pub fn fail_icons(&self, fail: bool) {
+        self.state().failing_icons = fail;
+    }
+
+    fn send(&self, event: SystrayEvent) -> bool {
+        match self.state().event_tx {
+            Some(ref tx) => tx.send(event).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn timer_count(&self) -> usize {
+        self.state().timers.len()
+    }
+
+    // Moves the clock forward, running every timer that comes due on the way
+    // in order and on the calling thread.
+    pub fn advance(&self, by: Duration) {
+        let until = self.state().now + by;
+        loop {
+            let (id, mut tick) = {
+                let mut state = self.state();
+                let next = state.timers.iter_mut()
+                    .filter(|t| t.due <= until && t.tick.is_some())
+                    .min_by_key(|t| t.due);
+                match next {
+                    Some(timer) => {
+                        let due = timer.due;
+                        timer.due += timer.interval;
+                        let id = timer.id;
+                        let tick = timer.tick.take().unwrap();
+                        state.now = due;
+                        (id, tick)
+                    }
+                    None => break,
+                }
+            };
+            let keep = tick();
+            let mut state = self.state();
+            // The tick may have removed its timer itself.
+            let pos = state.timers.iter().position(|t| t.id == id && t.tick.is_none());
+            if let Some(pos) = pos {
+                if keep {
+                    state.timers[pos].tick = Some(tick);
+                } else {
+                    state.timers.remove(pos);
+                }
+            }
+        }
+        self.state().now = until;
+    }
+
+    // Moves the mark of check and radio items first, as hosts do. Submenus
+    // only open, there is nothing to send for them.
+    pub fn click_menu_item(&self, item_idx: u32) -> bool {
+        {
+            let mut state = self.state();
+            if let Some(pos) = state.menu_pos(item_idx) {
+                let radio = match state.menu[pos].1 {
+                    MockMenuEntry::Check(_, _, ref mut checked) => {
+                        *checked = !*checked;
+                        false
+                    }
+                    MockMenuEntry::Radio(..) => true,
+                    MockMenuEntry::Submenu(..) => return false,
+                    _ => false,
+                };
+                if radio {
+                    state.check_radio(pos);
+                }
+            }
+        }
+        self.send(SystrayEvent::MenuItemClick(item_idx))
+    }
+
+    pub fn left_click(&self) -> bool {
+        self.send(SystrayEvent::LeftButtonClick)
+    }
+
+    // Forget everything like a crashed host would, then announce the new one.
+    pub fn restart_host(&self) -> bool {
+        {
+            let mut state = self.state();
+            state.icon = None;
+            state.visible = false;
+            state.tooltip = "".to_string();
+            state.label = "".to_string();
+            state.label_guide = "".to_string();
+            state.menu.clear();
+            state.disabled.clear();
+        }
+        self.send(SystrayEvent::HostChanged)
+    }
+}
+
+pub struct Window {
+    tray: MockTray,
+}
+
+impl Window {
+    pub fn new(event_tx: Sender<SystrayEvent>,
+               config: &TrayConfig,
+               tray: MockTray) -> Result<Window, SystrayError> {
+        {
+            let mut state = tray.state();
+            state.id = config.id.clone();
+            state.title = config.title.clone();
+            state.category = config.category;
+            state.event_tx = Some(event_tx);
+        }
+        Ok(Window {
+            tray: tray
+        })
+    }
+}
+
+impl BackendWindow for Window {
+    fn capabilities(&self) -> Capabilities {
+        self.tray.state().capabilities
+    }
+
+    fn set_timeout(&mut self, _: Option<Duration>) {
+    }
+
+    fn add_menu_entry(&self, item_idx: u32, parent: Option<u32>, item_name: &str,
+                      kind: MenuItemKind) -> Result<(), SystrayError> {
+        let mut state = self.tray.state();
+        let supported = match kind {
+            MenuItemKind::Plain => true,
+            MenuItemKind::Check(_) | MenuItemKind::Radio(_) => state.capabilities.check_items,
+            MenuItemKind::Submenu => state.capabilities.submenus,
+        };
+        if !supported {
+            return Err(SystrayError::NotImplementedError);
+        }
+        state.check_parent(parent)?;
+        let name = item_name.to_string();
+        let entry = match kind {
+            MenuItemKind::Plain => MockMenuEntry::Item(item_idx, name),
+            MenuItemKind::Check(checked) => MockMenuEntry::Check(item_idx, name, checked),
+            MenuItemKind::Radio(checked) => MockMenuEntry::Radio(item_idx, name, checked),
+            MenuItemKind::Submenu => MockMenuEntry::Submenu(item_idx, name, Vec::new()),
+        };
+        let pos = match state.menu_pos(item_idx) {
+            Some(pos) => {
+                state.menu[pos].1 = entry;
+                pos
+            }
+            None => {
+                state.menu.push((parent, entry));
+                state.menu.len() - 1
+            }
+        };
+        if kind == MenuItemKind::Radio(true) {
+            state.check_radio(pos);
+        }
+        Ok(())
+    }
+
+    fn add_menu_separator(&self, item_idx: u32, parent: Option<u32>) -> Result<(), SystrayError> {
+        let mut state = self.tray.state();
+        state.check_parent(parent)?;
+        let entry = (parent, MockMenuEntry::Separator(item_idx));
+        if !state.menu.contains(&entry) {
+            state.menu.push(entry);
+        }
+        Ok(())
+    }
+
+    fn remove_menu_entry(&self, item_idx: u32) -> Result<(), SystrayError> {
+        let mut state = self.tray.state();
+        let len = state.menu.len();
+        state.menu.retain(|&(_, ref entry)| entry.idx() != item_idx);
+        if state.menu.len() == len {
+            return Err(SystrayError::UnknownMenuItem(item_idx));
+        }
+        state.disabled.remove(&item_idx);
+        Ok(())
+    }
+
+    fn set_menu_item_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), SystrayError> {
+        let mut state = self.tray.state();
+        if enabled {
+            state.disabled.remove(&item_idx);
+        } else {
+            state.disabled.insert(item_idx);
+        }
+        Ok(())
+    }
+
+    fn set_icon_from_file(&self, file: &str) -> Result<(), SystrayError> {
+        let mut state = self.tray.state();
+        if state.failing_icons {
+            return Err(SystrayError::OsError(format!("Can't load {}", file)));
+        }
+        state.icon = Some(IconResource::File(file.to_string()));
+        state.visible = true;
+        Ok(())
+    }
+
+    fn set_icon_from_resource(&self, resource: &str) -> Result<(), SystrayError> {
+        let mut state = self.tray.state();
+        if state.failing_icons {
+            return Err(SystrayError::OsError(format!("Can't load {}", resource)));
+        }
+        state.icon = Some(IconResource::Resource(resource.to_string()));
+        state.visible = true;
+        Ok(())
+    }
+
+    fn icon_name(&self) -> Result<Option<String>, SystrayError> {
+        Ok(self.tray.state().icon.as_ref().map(|icon| match *icon {
+            IconResource::File(ref name) | IconResource::Resource(ref name) => name.clone(),
+        }))
+    }
+
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), SystrayError> {
+        self.tray.state().tooltip = tooltip.to_string();
+        Ok(())
+    }
+
+    fn set_label(&self, label: &str, guide: &str) -> Result<(), SystrayError> {
+        let mut state = self.tray.state();
+        if !state.capabilities.label {
+            return Err(SystrayError::NotImplementedError);
+        }
+        state.label = label.to_string();
+        state.label_guide = guide.to_string();
+        Ok(())
+    }
+
+    fn notify(&self, title: &str, body: &str) -> Result<(), SystrayError> {
+        let mut state = self.tray.state();
+        if !state.capabilities.notifications {
+            return Err(SystrayError::NotImplementedError);
+        }
+        state.notifications.push((title.to_string(), body.to_string()));
+        Ok(())
+    }
+
+    fn set_attention(&self, attention: bool) -> Result<(), SystrayError> {
+        let mut state = self.tray.state();
+        if !state.capabilities.attention {
+            return Err(SystrayError::NotImplementedError);
+        }
+        state.attention = attention;
+        Ok(())
+    }
+
+    fn set_attention_icon(&self, icon: &IconResource) -> Result<(), SystrayError> {
+        let mut state = self.tray.state();
+        if !state.capabilities.attention {
+            return Err(SystrayError::NotImplementedError);
+        }
+        state.attention_icon = Some(icon.clone());
+        Ok(())
+    }
+
+    fn delete_icon(&self) -> Result<(), SystrayError> {
+        self.tray.state().visible = false;
+        Ok(())
+    }
+
+    fn add_timer(&self, timer_id: u32, interval: Duration, tick: TimerTick) -> Result<(), SystrayError> {
+        interval_ms(interval)?;
+        let mut state = self.tray.state();
+        // A zero interval would keep advance() busy forever.
+        let interval = cmp::max(interval, Duration::from_millis(1));
+        let due = state.now + interval;
+        state.timers.push(MockTimer {
+            id: timer_id,
+            due: due,
+            interval: interval,
+            tick: Some(tick),
+        });
+        Ok(())
+    }
+
+    fn remove_timer(&self, timer_id: u32) -> Result<(), SystrayError> {
+        self.tray.state().timers.retain(|t| t.id != timer_id);
+        Ok(())
+    }
+
+    fn quit(&mut self) {
+        // Dropping the sender is what a real backend's thread exiting does.
+        let mut state = self.tray.state();
+        state.event_tx = None;
+        state.timers.clear();
+    }
+}
@@ -1,3 +1,6 @@
+use std::time::Duration;
+use {Capabilities, IconResource, MenuItemKind, SystrayError};
+
 #[cfg(target_os = "windows")]
 #[path="win32/mod.rs"]
 pub mod api;
@@ -9,3 +12,60 @@ pub mod api;
 #[cfg(target_os = "macos")]
 #[path="cocoa/mod.rs"]
 pub mod api;
+
+// Only there for tests, ours and applications', not part of the API.
+#[cfg(feature = "mock")]
+#[doc(hidden)]
+pub mod mock;
+
+// Called on the backend's loop every time a timer fires. Returning false
+// stops the timer.
+pub type TimerTick = Box<dyn FnMut() -> bool + Send>;
+
+// Timer intervals in milliseconds, as the backends' loops take them.
+// Anything past u32 is rejected rather than wrapped into a short one.
+pub fn interval_ms(interval: Duration) -> Result<u32, SystrayError> {
+    let ms = interval.as_secs()
+        .checked_mul(1000)
+        .and_then(|ms| ms.checked_add((interval.subsec_nanos() / 1_000_000) as u64));
+    match ms {
+        Some(ms) if ms <= u32::max_value() as u64 => Ok(ms as u32),
+        _ => Err(SystrayError::IntervalTooLong),
+    }
+}
+
+// Everything the core asks of a backend once it has been created. Each
+// backend has its own new(), since what it needs to get going differs.
+pub trait BackendWindow: Send {
+    fn capabilities(&self) -> Capabilities;
+    fn set_timeout(&mut self, timeout: Option<Duration>);
+    // Adds an item to the submenu item parent, or to the tray menu for None.
+    // For an index it already has the label and mark are updated instead.
+    // Radio items of the same menu are one group, checking one clears the
+    // others. Kinds the backend doesn't have are a NotImplementedError, see
+    // Capabilities::check_items and submenus.
+    fn add_menu_entry(&self, item_idx: u32, parent: Option<u32>, item_name: &str,
+                      kind: MenuItemKind) -> Result<(), SystrayError>;
+    fn add_menu_separator(&self, item_idx: u32, parent: Option<u32>) -> Result<(), SystrayError>;
+    // Takes an item or separator out of the menu. Its index is not used
+    // again. Submenus are only removed once their entries are.
+    fn remove_menu_entry(&self, item_idx: u32) -> Result<(), SystrayError>;
+    fn set_menu_item_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), SystrayError>;
+    fn set_icon_from_file(&self, file: &str) -> Result<(), SystrayError>;
+    fn set_icon_from_resource(&self, resource: &str) -> Result<(), SystrayError>;
+    // The file or resource name the host was last given for the icon. Only
+    // tests ask, to see a refresh went through.
+    fn icon_name(&self) -> Result<Option<String>, SystrayError>;
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), SystrayError>;
+    fn set_label(&self, label: &str, guide: &str) -> Result<(), SystrayError>;
+    fn notify(&self, title: &str, body: &str) -> Result<(), SystrayError>;
+    // Asks the host to make the icon stand out, see Capabilities::attention.
+    fn set_attention(&self, attention: bool) -> Result<(), SystrayError>;
+    // What the icon looks like while it asks for attention.
+    fn set_attention_icon(&self, icon: &IconResource) -> Result<(), SystrayError>;
+    fn delete_icon(&self) -> Result<(), SystrayError>;
+    // Timers go away with the backend on quit.
+    fn add_timer(&self, timer_id: u32, interval: Duration, tick: TimerTick) -> Result<(), SystrayError>;
+    fn remove_timer(&self, timer_id: u32) -> Result<(), SystrayError>;
+    fn quit(&mut self);
+}
@@ -1,18 +1,22 @@
 mod winapipatch;
 use self::winapipatch::*;
-use {SystrayEvent, SystrayError};
+use {SystrayEvent, SystrayError, Capabilities, IconResource, MenuItemKind, TrayConfig};
+use api::{interval_ms, BackendWindow, TimerTick};
 use std;
 use std::sync::mpsc::{channel, Sender};
 use std::os::windows::ffi::OsStrExt;
 use std::ffi::OsStr;
 use std::thread;
+use std::time::Duration;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use winapi;
 use winapi::{MENUITEMINFOW, UINT};
 use user32;
 use kernel32;
 use winapi::windef::{HWND, HMENU, HICON, HBRUSH, HBITMAP};
 use winapi::winnt::{LPCWSTR};
+use winapi::basetsd::UINT_PTR;
 use winapi::minwindef::{DWORD, WPARAM, LPARAM, LRESULT, HINSTANCE, TRUE, PBYTE};
 use winapi::winuser::{WNDCLASSW, WS_OVERLAPPEDWINDOW, CW_USEDEFAULT, LR_DEFAULTCOLOR};
 
@@ -20,6 +24,19 @@ use winapi::winuser::{WNDCLASSW, WS_OVERLAPPEDWINDOW, CW_USEDEFAULT, LR_DEFAULTC
 // doing SetLongPtr tho.
 thread_local!(static WININFO_STASH: RefCell<Option<WindowsLoopData>> = RefCell::new(None));
 
+// SetTimer only works on the thread owning the window, so timers are posted
+// over and their ticks kept here, by timer id.
+thread_local!(static TIMERS: RefCell<HashMap<u32, TimerTick>> = RefCell::new(HashMap::new()));
+
+const WM_ADD_TIMER: UINT = winapi::WM_USER + 2;
+const WM_REMOVE_TIMER: UINT = winapi::WM_USER + 3;
+
+struct TimerRequest {
+    timer_id: u32,
+    ms: UINT,
+    tick: TimerTick,
+}
+
 fn to_wstring(str : &str) -> Vec<u16> {
     OsStr::new(str).encode_wide().chain(Some(0).into_iter()).collect::<Vec<_>>()
 }
@@ -37,7 +54,8 @@ unsafe impl Sync for WindowInfo {}
 #[derive(Clone)]
 struct WindowsLoopData {
     pub info: WindowInfo,
-    pub tx: Sender<SystrayEvent>
+    pub tx: Sender<SystrayEvent>,
+    pub taskbar_created: UINT,
 }
 
 unsafe fn get_win_os_error(msg: &str) -> SystrayError {
@@ -99,12 +117,60 @@ unsafe extern "system" fn window_proc(h_wnd :HWND,
                 });
             }
     }
+    if msg == WM_ADD_TIMER {
+        let request = Box::from_raw(l_param as *mut TimerRequest);
+        if user32::SetTimer(h_wnd, request.timer_id as UINT_PTR, request.ms, None) == 0 {
+            warn!("{}", get_win_os_error("Error setting timer"));
+        } else {
+            TIMERS.with(|timers| {
+                timers.borrow_mut().insert(request.timer_id, request.tick);
+            });
+        }
+        return 0;
+    }
+    if msg == WM_REMOVE_TIMER {
+        let timer_id = w_param as u32;
+        user32::KillTimer(h_wnd, timer_id as UINT_PTR);
+        TIMERS.with(|timers| timers.borrow_mut().remove(&timer_id));
+        return 0;
+    }
+    if msg == winapi::winuser::WM_TIMER {
+        let timer_id = w_param as u32;
+        // Out of the map while it runs, the tick may well add timers.
+        let tick = TIMERS.with(|timers| timers.borrow_mut().remove(&timer_id));
+        if let Some(mut tick) = tick {
+            if tick() {
+                TIMERS.with(|timers| timers.borrow_mut().insert(timer_id, tick));
+            } else {
+                user32::KillTimer(h_wnd, timer_id as UINT_PTR);
+            }
+        }
+        return 0;
+    }
     if msg == winapi::winuser::WM_DESTROY {
+        TIMERS.with(|timers| {
+            for (timer_id, _) in timers.borrow_mut().drain() {
+                user32::KillTimer(h_wnd, timer_id as UINT_PTR);
+            }
+        });
         user32::PostQuitMessage(0);
     }
+    // Explorer broadcasts this after it restarts, by which point it has
+    // forgotten every icon it was showing.
+    WININFO_STASH.with(|stash| {
+        let stash = stash.borrow();
+        let stash = stash.as_ref();
+        if let Some(stash) = stash {
+            if stash.taskbar_created != 0 && msg == stash.taskbar_created {
+                stash.tx.send(SystrayEvent::HostChanged).ok();
+            }
+        }
+    });
     return user32::DefWindowProcW(h_wnd, msg, w_param, l_param);
 }
 
+// Icons are told apart by window and uID together. Every tray has a window
+// of its own, so the same uID does for all of them.
 fn get_nid_struct(hwnd : &HWND) -> NOTIFYICONDATAW {
     NOTIFYICONDATAW {
         cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as DWORD,
@@ -147,8 +213,8 @@ fn get_menu_item_struct() -> MENUITEMINFOW {
     }
 }
 
-unsafe fn init_window() -> Result<WindowInfo, SystrayError> {
-    let class_name = to_wstring("my_tray_window");
+unsafe fn init_window(title: &str) -> Result<WindowInfo, SystrayError> {
+    let class_name = to_wstring("systray_rs_window");
     let hinstance : HINSTANCE = kernel32::GetModuleHandleA(std::ptr::null_mut());
     let wnd = WNDCLASSW {
         style: 0,
@@ -164,12 +230,15 @@ unsafe fn init_window() -> Result<WindowInfo, SystrayError> {
         lpszMenuName: 0 as LPCWSTR,
         lpszClassName: class_name.as_ptr(),
     };
-    if user32::RegisterClassW(&wnd) == 0 {
+    // One class for all the tray windows in the process, whichever comes
+    // first registers it.
+    if user32::RegisterClassW(&wnd) == 0 &&
+        kernel32::GetLastError() != winapi::winerror::ERROR_CLASS_ALREADY_EXISTS {
         return Err(get_win_os_error("Error creating window class"));
     }
     let hwnd = user32::CreateWindowExW(0,
                                        class_name.as_ptr(),
-                                       to_wstring("rust_systray_window").as_ptr(),
+                                       to_wstring(title).as_ptr(),
                                        WS_OVERLAPPEDWINDOW,
                                        CW_USEDEFAULT,
                                        0,
@@ -232,11 +301,12 @@ pub struct Window {
 }
 
 impl Window {
-    pub fn new(event_tx: Sender<SystrayEvent>) -> Result<Window, SystrayError> {
+    pub fn new(event_tx: Sender<SystrayEvent>, config: &TrayConfig) -> Result<Window, SystrayError> {
         let (tx, rx) = channel();
+        let title = config.title.clone();
         let windows_loop = thread::spawn(move || {
             unsafe {
-                let i = init_window();
+                let i = init_window(&title);
                 let k;
                 match i {
                     Ok(j) => {
@@ -249,21 +319,27 @@ impl Window {
                         return;
                     }
                 };
+                let taskbar_created =
+                    user32::RegisterWindowMessageW(to_wstring("TaskbarCreated").as_ptr());
                 WININFO_STASH.with(|stash| {
                     let data = WindowsLoopData {
                         info: k,
-                        tx: event_tx
+                        tx: event_tx,
+                        taskbar_created: taskbar_created
                     };
                     (*stash.borrow_mut()) = Some(data);
                 });
                 run_loop();
             }
         });
-        let info = match rx.recv().unwrap() {
-            Ok(i) => i,
-            Err(e) => {
+        let info = match rx.recv() {
+            Ok(Ok(i)) => i,
+            Ok(Err(e)) => {
                 return Err(e);
             }
+            Err(_) => {
+                return Err(SystrayError::BackendGone);
+            }
         };
         let w = Window {
             info: info,
@@ -272,7 +348,129 @@ impl Window {
         Ok(w)
     }
 
-    pub fn quit(&mut self) {
+    fn has_menu_item(&self, item_idx: u32) -> bool {
+        let mut item = get_menu_item_struct();
+        unsafe {
+            GetMenuItemInfoW(self.info.hmenu, item_idx, 0,
+                             &mut item as *mut winapi::MENUITEMINFOW) != 0
+        }
+    }
+
+    fn add_icon(&self,icon: HICON) -> Result<(), SystrayError> {
+        unsafe {
+            let mut nid = get_nid_struct(&self.info.hwnd);
+            nid.uID = 0x1;
+            nid.uFlags = winapi::NIF_MESSAGE;
+            nid.hIcon = icon;
+            nid.uCallbackMessage = winapi::WM_USER + 1;
+
+            if Shell_NotifyIconW(winapi::NIM_ADD,
+                                        &mut nid as *mut NOTIFYICONDATAW) == 0 {
+                return Err(get_win_os_error("Error adding menu icon add "));
+            }
+
+            nid.uFlags = winapi::NIF_ICON;
+            nid.hIcon = icon;
+            nid.uCallbackMessage = winapi::WM_USER + 1;
+
+            if Shell_NotifyIconW(winapi::NIM_MODIFY,
+                                        &mut nid as *mut NOTIFYICONDATAW) == 0 {
+                return Err(get_win_os_error("Error adding menu icon modify"));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_icon(&self, icon: HICON) -> Result<(), SystrayError> {
+        let _ = self.delete_icon();
+        self.add_icon(icon)
+    }
+
+    pub fn set_icon_from_buffer(&self, buffer: &[u8], width: u32, height: u32) -> Result<(), SystrayError> {
+        let offset = unsafe {
+            user32::LookupIconIdFromDirectoryEx(
+                buffer.as_ptr() as PBYTE,
+                TRUE,
+                width as i32,
+                height as i32,
+                LR_DEFAULTCOLOR
+            )
+        };
+
+        if offset != 0 {
+            let icon_data = &buffer[offset as usize ..];
+            let hicon = unsafe {
+                user32::CreateIconFromResourceEx(
+                    icon_data.as_ptr() as PBYTE,
+                    0,
+                    TRUE,
+                    0x30000,
+                    width as i32,
+                    height as i32,
+                    LR_DEFAULTCOLOR
+                )
+            };
+
+            if hicon == std::ptr::null_mut() as HICON {
+                return Err( unsafe { get_win_os_error("Cannot load icon from the buffer") } );
+            }
+
+            self.set_icon(hicon)
+        } else {
+            Err( unsafe { get_win_os_error("Error setting icon from buffer") })
+        }
+    }
+
+    pub fn shutdown(&self) -> Result<(), SystrayError> {
+        self.delete_icon()
+    }
+}
+
+impl BackendWindow for Window {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            left_click: true,
+            tooltip: true,
+            icon_from_buffer: true,
+            notifications: true,
+            hide_show: true,
+            ..Capabilities::default()
+        }
+    }
+
+    // Everything runs straight on the caller's thread here, there is nothing
+    // to wait for.
+    fn set_timeout(&mut self, _: Option<Duration>) {
+    }
+
+    fn add_timer(&self, timer_id: u32, interval: Duration, tick: TimerTick) -> Result<(), SystrayError> {
+        let ms = interval_ms(interval)?;
+        let request = Box::into_raw(Box::new(TimerRequest {
+            timer_id: timer_id,
+            ms: ms,
+            tick: tick,
+        }));
+        unsafe {
+            if user32::PostMessageW(self.info.hwnd, WM_ADD_TIMER,
+                                    0 as WPARAM, request as LPARAM) == 0 {
+                drop(Box::from_raw(request));
+                return Err(get_win_os_error("Error adding timer"));
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_timer(&self, timer_id: u32) -> Result<(), SystrayError> {
+        unsafe {
+            if user32::PostMessageW(self.info.hwnd, WM_REMOVE_TIMER,
+                                    timer_id as WPARAM, 0 as LPARAM) == 0 {
+                return Err(get_win_os_error("Error removing timer"));
+            }
+        }
+        Ok(())
+    }
+
+    fn quit(&mut self) {
         unsafe {
             user32::PostMessageW(self.info.hwnd, winapi::WM_DESTROY,
                                  0 as WPARAM, 0 as LPARAM);
@@ -282,7 +480,7 @@ impl Window {
         }
     }
 
-    pub fn set_tooltip(&self, tooltip: &String) -> Result<(), SystrayError> {
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), SystrayError> {
 
         use widestring::WideCString;
 
@@ -305,7 +503,17 @@ impl Window {
         Ok(())
     }
 
-    pub fn add_menu_entry(&self, item_idx: u32, item_name: &String) -> Result<(), SystrayError> {
+    // The notification area has no room for text next to the icon.
+    fn set_label(&self, _: &str, _: &str) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+
+    // Only plain items in the one popup menu so far.
+    fn add_menu_entry(&self, item_idx: u32, parent: Option<u32>, item_name: &str,
+                      kind: MenuItemKind) -> Result<(), SystrayError> {
+        if parent.is_some() || kind != MenuItemKind::Plain {
+            return Err(SystrayError::NotImplementedError);
+        }
         let mut st = to_wstring(item_name);
         let mut item = get_menu_item_struct();
         item.fMask = MIIM_FTYPE | MIIM_STRING | MIIM_ID | MIIM_STATE;
@@ -314,6 +522,17 @@ impl Window {
         item.dwTypeData = st.as_mut_ptr();
         item.cch = (item_name.len() * 2) as u32;
         unsafe {
+            // Known ids are relabelled in place, which is what a state replay
+            // after a host restart needs.
+            if self.has_menu_item(item_idx) {
+                if SetMenuItemInfoW(self.info.hmenu,
+                                    item_idx,
+                                    0,
+                                    &mut item as *mut winapi::MENUITEMINFOW) == 0 {
+                    return Err(get_win_os_error("Error updating menu item"));
+                }
+                return Ok(());
+            }
             if user32::InsertMenuItemW(self.info.hmenu,
                                        item_idx,
                                        1,
@@ -324,9 +543,46 @@ impl Window {
         Ok(())
     }
 
-    pub fn add_menu_separator(&self, item_idx: u32) -> Result<(), SystrayError> {
+    fn set_menu_item_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), SystrayError> {
+        if !self.has_menu_item(item_idx) {
+            return Err(SystrayError::UnknownMenuItem(item_idx));
+        }
         let mut item = get_menu_item_struct();
-        item.fMask = MIIM_FTYPE;
+        item.fMask = MIIM_STATE;
+        item.fState = if enabled { MFS_ENABLED } else { MFS_DISABLED };
+        unsafe {
+            if SetMenuItemInfoW(self.info.hmenu,
+                                item_idx,
+                                0,
+                                &mut item as *mut winapi::MENUITEMINFOW) == 0 {
+                return Err(get_win_os_error("Error enabling menu item"));
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_menu_entry(&self, item_idx: u32) -> Result<(), SystrayError> {
+        if !self.has_menu_item(item_idx) {
+            return Err(SystrayError::UnknownMenuItem(item_idx));
+        }
+        unsafe {
+            if user32::DeleteMenu(self.info.hmenu, item_idx, MF_BYCOMMAND) == 0 {
+                return Err(get_win_os_error("Error removing menu item"));
+            }
+        }
+        Ok(())
+    }
+
+    fn add_menu_separator(&self, item_idx: u32, parent: Option<u32>) -> Result<(), SystrayError> {
+        if parent.is_some() {
+            return Err(SystrayError::NotImplementedError);
+        }
+        if self.has_menu_item(item_idx) {
+            return Ok(());
+        }
+        let mut item = get_menu_item_struct();
+        // With an id, so it can be found again to remove it.
+        item.fMask = MIIM_FTYPE | MIIM_ID;
         item.fType = MFT_SEPARATOR;
         item.wID = item_idx;
         unsafe {
@@ -340,37 +596,37 @@ impl Window {
         Ok(())
     }
 
-    fn add_icon(&self,icon: HICON) -> Result<(), SystrayError> {
+    // Shown as a balloon, or a toast on newer Windows, next to the icon.
+    fn notify(&self, title: &str, body: &str) -> Result<(), SystrayError> {
+        let mut nid = get_nid_struct(&self.info.hwnd);
+        let title = to_wstring(title);
+        let body = to_wstring(body);
+        // Both have to end in a nul, cut them short if needed.
+        for (i, c) in title.iter().take(nid.szInfoTitle.len() - 1).enumerate() {
+            nid.szInfoTitle[i] = *c;
+        }
+        for (i, c) in body.iter().take(nid.szInfo.len() - 1).enumerate() {
+            nid.szInfo[i] = *c;
+        }
+        nid.uFlags = winapi::NIF_INFO;
         unsafe {
-            let mut nid = get_nid_struct(&self.info.hwnd);
-            nid.uID = 0x1;
-            nid.uFlags = winapi::NIF_MESSAGE;
-            nid.hIcon = icon;
-            nid.uCallbackMessage = winapi::WM_USER + 1;
-
-            if Shell_NotifyIconW(winapi::NIM_ADD,
-                                        &mut nid as *mut NOTIFYICONDATAW) == 0 {
-                return Err(get_win_os_error("Error adding menu icon add "));
-            }
-
-            nid.uFlags = winapi::NIF_ICON;
-            nid.hIcon = icon;
-            nid.uCallbackMessage = winapi::WM_USER + 1;
-
             if Shell_NotifyIconW(winapi::NIM_MODIFY,
-                                        &mut nid as *mut NOTIFYICONDATAW) == 0 {
-                return Err(get_win_os_error("Error adding menu icon modify"));
+                                 &mut nid as *mut NOTIFYICONDATAW) == 0 {
+                return Err(get_win_os_error("Error showing notification"));
             }
         }
         Ok(())
     }
 
-    fn set_icon(&self, icon: HICON) -> Result<(), SystrayError> {
-        let _ = self.delete_icon();
-        self.add_icon(icon)
+    fn set_attention(&self, _: bool) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
     }
 
-    pub fn delete_icon(&self) -> Result<(), SystrayError> {
+    fn set_attention_icon(&self, _: &IconResource) -> Result<(), SystrayError> {
+        Err(SystrayError::NotImplementedError)
+    }
+
+    fn delete_icon(&self) -> Result<(), SystrayError> {
         unsafe {
             let mut nid = get_nid_struct(&self.info.hwnd);
             nid.uFlags = winapi::NIF_ICON;
@@ -383,7 +639,7 @@ impl Window {
         Ok(())
     }
 
-    pub fn set_icon_from_resource(&self, resource_name: &String) -> Result<(), SystrayError> {
+    fn set_icon_from_resource(&self, resource_name: &str) -> Result<(), SystrayError> {
         let icon;
         unsafe {
             icon = user32::LoadImageW(self.info.hinstance,
@@ -399,7 +655,7 @@ impl Window {
         self.set_icon(icon)
     }
 
-    pub fn set_icon_from_file(&self, icon_file: &String) -> Result<(), SystrayError> {
+    fn set_icon_from_file(&self, icon_file: &str) -> Result<(), SystrayError> {
         let wstr_icon_file = to_wstring(&icon_file);
         let hicon;
         unsafe {
@@ -412,43 +668,8 @@ impl Window {
         self.set_icon(hicon)
     }
 
-    pub fn set_icon_from_buffer(&self, buffer: &[u8], width: u32, height: u32) -> Result<(), SystrayError> {
-        let offset = unsafe {
-            user32::LookupIconIdFromDirectoryEx(
-                buffer.as_ptr() as PBYTE,
-                TRUE,
-                width as i32,
-                height as i32,
-                LR_DEFAULTCOLOR
-            )
-        };
-
-        if offset != 0 {
-            let icon_data = &buffer[offset as usize ..];
-            let hicon = unsafe {
-                user32::CreateIconFromResourceEx(
-                    icon_data.as_ptr() as PBYTE,
-                    0,
-                    TRUE,
-                    0x30000,
-                    width as i32,
-                    height as i32,
-                    LR_DEFAULTCOLOR
-                )
-            };
-
-            if hicon == std::ptr::null_mut() as HICON {
-                return Err( unsafe { get_win_os_error("Cannot load icon from the buffer") } );
-            }
-
-            self.set_icon(hicon)
-        } else {
-            Err( unsafe { get_win_os_error("Error setting icon from buffer") })
-        }
-    }
-
-    pub fn shutdown(&self) -> Result<(), SystrayError> {
-        self.delete_icon()
+    fn icon_name(&self) -> Result<Option<String>, SystrayError> {
+        Err(SystrayError::NotImplementedError)
     }
 }
 
@@ -46,6 +46,7 @@ extern "system" {
     pub fn GetMenuItemInfoA(hMenu: HMENU, uItem: UINT, fByPosition: BOOL, lpmii: LPMENUITEMINFOA) -> BOOL;
     pub fn GetMenuItemInfoW(hMenu: HMENU, uItem: UINT, fByPosition: BOOL, lpmii: LPMENUITEMINFOW) -> BOOL;
     pub fn SetMenuInfo(hMenu: HMENU, lpcmi: LPCMENUINFO) -> BOOL;
+    pub fn SetMenuItemInfoW(hMenu: HMENU, uItem: UINT, fByPosition: BOOL, lpmii: LPMENUITEMINFOW) -> BOOL;
     pub fn TrackPopupMenu(hMenu: HMENU, uFlags: UINT, x: c_int, y: c_int, nReserved: c_int,
                           hWnd: HWND, prcRect: *const RECT);
     pub fn TrackPopupMenuEx(hMenu: HMENU, fuFlags: UINT, x: c_int, y: c_int, hWnd: HWND,
@@ -123,6 +124,8 @@ pub const MIIM_STRING: UINT = 0x00000040;
 pub const MIIM_SUBMENU: UINT = 0x00000004;
 pub const MIIM_TYPE: UINT = 0x00000010;
 
+pub const MF_BYCOMMAND: UINT = 0x00000000;
+
 pub const MFT_BITMAP: UINT = 0x00000004;
 pub const MFT_MENUBARBREAK: UINT = 0x00000020;
 pub const MFT_MENUBREAK: UINT = 0x00000040;
@@ -0,0 +1,156 @@
+// Shows a tray for shell scripts and other tools that can't use the library
+// directly. The tray starts out as described by the spec file, if one is
+// given (see systray::config), and then follows the commands read from stdin, see
+// systray::protocol. Events and failed commands come back as lines on
+// stdout:
+//
+//     systray backup.toml | while read event id; do ...; done
+//
+// With --json-rpc both directions speak systray::json_rpc instead, for
+// programs that want a tray helper process. With --control <socket> other
+// processes can send commands too, see systray::control. That one is Unix
+// only, elsewhere it is refused as a bad command line.
+//
+// The tray outlives stdin, it is gone after a quit command or when the host
+// shuts it down. Exits with 1 on a bad command line or spec, and with 2 when
+// there is no tray to show it in.
+
+extern crate systray;
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::sync::mpsc::channel;
+use std::thread;
+use systray::{Builder, SystrayEvent};
+use systray::config::TraySpec;
+use systray::json_rpc::{JsonRpc, event_notification};
+use systray::protocol::{Command, event_line, error_line};
+
+const USAGE: &'static str = "usage: systray [--json-rpc] [--control socket] [spec.toml|spec.json|spec.yaml]";
+
+enum Input {
+    Line(String),
+    Event(SystrayEvent<String>),
+    // Anything else to write out as is.
+    Output(String),
+}
+
+fn fail(code: i32, msg: &str) -> ! {
+    let _ = writeln!(io::stderr(), "systray: {}", msg);
+    process::exit(code)
+}
+
+fn main() {
+    let mut json_rpc = false;
+    let mut control_path = None;
+    let mut spec_path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--json-rpc" => json_rpc = true,
+            "--control" => match args.next() {
+                Some(path) => control_path = Some(path),
+                None => fail(1, USAGE),
+            },
+            _ if arg.starts_with('-') || spec_path.is_some() => fail(1, USAGE),
+            _ => spec_path = Some(arg),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        if control_path.is_some() {
+            fail(1, "--control is only supported on Unix");
+        }
+    }
+    let spec = match spec_path {
+        Some(ref path) => match TraySpec::load(path) {
+            Ok(spec) => spec,
+            Err(e) => fail(1, &e.to_string()),
+        },
+        None => TraySpec::default(),
+    };
+    let (tx, rx) = channel();
+    let events = tx.clone();
+    let sink = move |event| {
+        events.send(Input::Event(event)).ok();
+    };
+    let mut app = match spec.build(Builder::new().event_sink(sink)) {
+        Ok(app) => app,
+        Err(e) => fail(2, &format!("no system tray available: {}", e)),
+    };
+    // Stops listening when main returns.
+    #[cfg(unix)]
+    let _control = match control_path {
+        Some(path) => match app.serve_control(&path) {
+            Ok(server) => Some(server),
+            Err(e) => fail(1, &format!("cannot listen for commands: {}", e)),
+        },
+        None => None,
+    };
+    let mut rpc = if json_rpc {
+        let (out_tx, out_rx) = channel();
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for line in out_rx.iter() {
+                if tx.send(Input::Output(line)).is_err() {
+                    break;
+                }
+            }
+        });
+        Some(JsonRpc::new(out_tx))
+    } else {
+        None
+    };
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => if tx.send(Input::Line(line)).is_err() { break },
+                Err(_) => break,
+            }
+        }
+    });
+    let stdout = io::stdout();
+    for input in rx.iter() {
+        let (lines, last) = match input {
+            Input::Line(line) => {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let reply = match rpc {
+                    Some(ref mut rpc) => rpc.handle_line(&mut app, &line).into_iter().collect(),
+                    None => Command::parse(&line)
+                        .and_then(|command| command.run(&mut app))
+                        .unwrap_or_else(|e| vec![error_line(&e)]),
+                };
+                (reply, false)
+            }
+            Input::Event(event) => {
+                let last = match event {
+                    SystrayEvent::Quit => true,
+                    _ => false,
+                };
+                if rpc.is_some() {
+                    (vec![event_notification(&event)], last)
+                } else {
+                    (vec![event_line(&event)], last)
+                }
+            }
+            Input::Output(line) => (vec![line], false),
+        };
+        let mut out = stdout.lock();
+        let written = lines.iter()
+            .map(|line| writeln!(out, "{}", line))
+            .collect::<io::Result<()>>()
+            .and_then(|_| out.flush());
+        // Nobody reading anymore, which is as good as being told to quit.
+        if written.is_err() || last {
+            break;
+        }
+    }
+}
@@ -0,0 +1,399 @@
+// Lets everything be configured up front, so the first thing the tray host
+// sees is the finished tray rather than an empty icon filling up.
+
+use std::env;
+use std::sync::mpsc::channel;
+use api;
+use api::BackendWindow;
+#[cfg(feature = "mock")]
+use api::mock::MockTray;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde::ser::{self, SerializeSeq};
+use sink::EventSink;
+use {Application, Callback, IconResource, IndicatorCategory, MenuItemKind, SystrayError, SystrayEvent,
+     TrayConfig, make_callback};
+
+pub enum Backend {
+    // Whatever this platform uses, see api::api.
+    Native,
+    // Join the GLib main loop the application already runs rather than
+    // starting gtk on a thread of our own. Build, use and drop the
+    // application on that loop's thread. Gtk only lives on one thread, so
+    // this doesn't mix with Native trays in the same process.
+    //
+    // Calls from other threads, those through a TrayHandle included, are
+    // queued for the loop and wait for it to run them, so they get the real
+    // result. Don't block the loop's thread on the tray meanwhile, say by
+    // calling the application from there while another thread's call is
+    // waiting: that call holds the tray and only gives up with Timeout once
+    // Application::set_backend_timeout has passed, without one never.
+    #[cfg(target_os = "linux")]
+    GlibMainContext,
+    // A backend without a tray host, for tests. Hidden like MockTray.
+    #[cfg(feature = "mock")]
+    #[doc(hidden)]
+    Mock(MockTray),
+}
+
+// Items with whether they are enabled.
+enum MenuItem<T> {
+    Item(String, MenuItemKind, bool, Callback<T>),
+    Payload(String, MenuItemKind, bool, T),
+    Submenu(String, bool, Menu<T>),
+    Separator,
+}
+
+pub struct Menu<T = u32> {
+    items: Vec<MenuItem<T>>,
+}
+
+impl<T> Menu<T> {
+    pub fn new() -> Menu<T> {
+        Menu {
+            items: Vec::new()
+        }
+    }
+
+    pub fn item<F>(mut self, item_name: &str, f: F) -> Menu<T>
+        where F: Fn(&mut Application<T>) -> () + 'static {
+        self.items.push(MenuItem::Item(item_name.to_string(), MenuItemKind::Plain, true, make_callback(f)));
+        self
+    }
+
+    // Clicks on this item arrive as MenuItemClick(payload).
+    pub fn item_with(mut self, item_name: &str, payload: T) -> Menu<T> {
+        self.items.push(MenuItem::Payload(item_name.to_string(), MenuItemKind::Plain, true, payload));
+        self
+    }
+
+    // Every click flips the mark, see MenuItemKind.
+    pub fn check_item<F>(mut self, item_name: &str, checked: bool, f: F) -> Menu<T>
+        where F: Fn(&mut Application<T>) -> () + 'static {
+        self.items.push(MenuItem::Item(item_name.to_string(), MenuItemKind::Check(checked), true, make_callback(f)));
+        self
+    }
+
+    pub fn check_item_with(mut self, item_name: &str, checked: bool, payload: T) -> Menu<T> {
+        self.items.push(MenuItem::Payload(item_name.to_string(), MenuItemKind::Check(checked), true, payload));
+        self
+    }
+
+    // The radio items of one menu are a group, a click moves the mark to
+    // the clicked one.
+    pub fn radio_item<F>(mut self, item_name: &str, checked: bool, f: F) -> Menu<T>
+        where F: Fn(&mut Application<T>) -> () + 'static {
+        self.items.push(MenuItem::Item(item_name.to_string(), MenuItemKind::Radio(checked), true, make_callback(f)));
+        self
+    }
+
+    pub fn radio_item_with(mut self, item_name: &str, checked: bool, payload: T) -> Menu<T> {
+        self.items.push(MenuItem::Payload(item_name.to_string(), MenuItemKind::Radio(checked), true, payload));
+        self
+    }
+
+    // An item opening a menu of its own. It sends no clicks.
+    pub fn submenu(mut self, item_name: &str, menu: Menu<T>) -> Menu<T> {
+        self.items.push(MenuItem::Submenu(item_name.to_string(), true, menu));
+        self
+    }
+
+    // Greys out the item added last, or not. Separators can't be clicked
+    // either way.
+    pub fn enabled(mut self, enabled: bool) -> Menu<T> {
+        match self.items.last_mut() {
+            Some(&mut MenuItem::Item(_, _, ref mut e, _)) |
+            Some(&mut MenuItem::Payload(_, _, ref mut e, _)) |
+            Some(&mut MenuItem::Submenu(_, ref mut e, _)) => *e = enabled,
+            _ => {}
+        }
+        self
+    }
+
+    pub fn separator(mut self) -> Menu<T> {
+        self.items.push(MenuItem::Separator);
+        self
+    }
+}
+
+// A Menu written out as a literal:
+//
+//     let menu = menu! {
+//         "Run now" => |app| run(app),
+//         "Open" => payload(Action::Open),
+//         "Paused" => check(false, payload(Action::Pause)),
+//         "Schedule" => submenu {
+//             "Daily" => radio(true, payload(Action::Daily)),
+//             "Never" => radio(false, |app| never(app)),
+//         },
+//         separator,
+//         "Quit" => |app| app.quit(),
+//     };
+//
+// Items take a callback, or a payload their clicks carry, check and radio
+// items their mark before that. Labels can be any expression that borrows as
+// a &str. Anything else is a compile error.
+#[macro_export]
+macro_rules! menu {
+    (@entries $menu:expr;) => { $menu };
+    (@entries $menu:expr; separator $(, $($rest:tt)*)?) => {
+        $crate::menu!(@entries $menu.separator(); $($($rest)*)?)
+    };
+    (@entries $menu:expr; $label:expr => payload($payload:expr) $(, $($rest:tt)*)?) => {
+        $crate::menu!(@entries $menu.item_with(&$label, $payload); $($($rest)*)?)
+    };
+    (@entries $menu:expr; $label:expr => check($checked:expr, payload($payload:expr)) $(, $($rest:tt)*)?) => {
+        $crate::menu!(@entries $menu.check_item_with(&$label, $checked, $payload); $($($rest)*)?)
+    };
+    (@entries $menu:expr; $label:expr => check($checked:expr, $f:expr) $(, $($rest:tt)*)?) => {
+        $crate::menu!(@entries $menu.check_item(&$label, $checked, $f); $($($rest)*)?)
+    };
+    (@entries $menu:expr; $label:expr => radio($checked:expr, payload($payload:expr)) $(, $($rest:tt)*)?) => {
+        $crate::menu!(@entries $menu.radio_item_with(&$label, $checked, $payload); $($($rest)*)?)
+    };
+    (@entries $menu:expr; $label:expr => radio($checked:expr, $f:expr) $(, $($rest:tt)*)?) => {
+        $crate::menu!(@entries $menu.radio_item(&$label, $checked, $f); $($($rest)*)?)
+    };
+    // Ahead of plain callbacks, which would take the braces for a struct.
+    (@entries $menu:expr; $label:expr => submenu { $($entries:tt)* } $(, $($rest:tt)*)?) => {
+        $crate::menu!(@entries $menu.submenu(&$label, $crate::menu!($($entries)*)); $($($rest)*)?)
+    };
+    (@entries $menu:expr; $label:expr => $f:expr $(, $($rest:tt)*)?) => {
+        $crate::menu!(@entries $menu.item(&$label, $f); $($($rest)*)?)
+    };
+    (@entries $menu:expr; $($entry:tt)*) => {
+        compile_error!(concat!("menu! can't make out \"", stringify!($($entry)*), "\""))
+    };
+    ($($entries:tt)*) => {
+        $crate::menu!(@entries $crate::Menu::new(); $($entries)*)
+    };
+}
+
+// How a Menu looks serialized, callback items have no place in it. Enabled
+// is only written out for disabled entries.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MenuEntryRepr<T, M> {
+    Item {
+        label: String,
+        #[serde(default = "enabled_by_default", skip_serializing_if = "is_enabled")]
+        enabled: bool,
+        payload: T,
+    },
+    Check {
+        label: String,
+        checked: bool,
+        #[serde(default = "enabled_by_default", skip_serializing_if = "is_enabled")]
+        enabled: bool,
+        payload: T,
+    },
+    Radio {
+        label: String,
+        checked: bool,
+        #[serde(default = "enabled_by_default", skip_serializing_if = "is_enabled")]
+        enabled: bool,
+        payload: T,
+    },
+    Submenu {
+        label: String,
+        #[serde(default = "enabled_by_default", skip_serializing_if = "is_enabled")]
+        enabled: bool,
+        entries: M,
+    },
+    Separator,
+}
+
+#[cfg(feature = "serde")]
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[cfg(feature = "serde")]
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
+#[cfg(feature = "serde")]
+impl<T: Serialize> Serialize for Menu<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.items.len()))?;
+        for item in &self.items {
+            let entry: MenuEntryRepr<&T, &Menu<T>> = match *item {
+                MenuItem::Item(ref label, _, _, _) => {
+                    return Err(ser::Error::custom(format!("menu item {:?} has a callback", label)));
+                }
+                MenuItem::Payload(ref label, MenuItemKind::Check(checked), enabled, ref payload) => {
+                    MenuEntryRepr::Check { label: label.clone(), checked: checked, enabled: enabled, payload: payload }
+                }
+                MenuItem::Payload(ref label, MenuItemKind::Radio(checked), enabled, ref payload) => {
+                    MenuEntryRepr::Radio { label: label.clone(), checked: checked, enabled: enabled, payload: payload }
+                }
+                MenuItem::Payload(ref label, _, enabled, ref payload) => {
+                    MenuEntryRepr::Item { label: label.clone(), enabled: enabled, payload: payload }
+                }
+                MenuItem::Submenu(ref label, enabled, ref menu) => {
+                    MenuEntryRepr::Submenu { label: label.clone(), enabled: enabled, entries: menu }
+                }
+                MenuItem::Separator => MenuEntryRepr::Separator,
+            };
+            seq.serialize_element(&entry)?;
+        }
+        seq.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Menu<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Menu<T>, D::Error> {
+        let entries: Vec<MenuEntryRepr<T, Menu<T>>> = Deserialize::deserialize(deserializer)?;
+        Ok(Menu {
+            items: entries.into_iter().map(|entry| match entry {
+                MenuEntryRepr::Item { label, enabled, payload } => {
+                    MenuItem::Payload(label, MenuItemKind::Plain, enabled, payload)
+                }
+                MenuEntryRepr::Check { label, checked, enabled, payload } => {
+                    MenuItem::Payload(label, MenuItemKind::Check(checked), enabled, payload)
+                }
+                MenuEntryRepr::Radio { label, checked, enabled, payload } => {
+                    MenuItem::Payload(label, MenuItemKind::Radio(checked), enabled, payload)
+                }
+                MenuEntryRepr::Submenu { label, enabled, entries } => MenuItem::Submenu(label, enabled, entries),
+                MenuEntryRepr::Separator => MenuItem::Separator,
+            }).collect()
+        })
+    }
+}
+
+pub struct Builder<T = u32> {
+    id: Option<String>,
+    title: Option<String>,
+    category: IndicatorCategory,
+    icon: Option<IconResource>,
+    tooltip: String,
+    menu: Menu<T>,
+    backend: Backend,
+    sink: Option<Box<dyn EventSink<T>>>,
+}
+
+// Hosts remember per-app settings by id, so default to something that at
+// least tells apps apart.
+fn default_id() -> String {
+    env::current_exe().ok()
+        .and_then(|p| p.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "systray-rs".to_string())
+}
+
+impl<T: Clone + Send + 'static> Builder<T> {
+    pub fn new() -> Builder<T> {
+        Builder {
+            id: None,
+            title: None,
+            category: IndicatorCategory::ApplicationStatus,
+            icon: None,
+            tooltip: "".to_string(),
+            menu: Menu::new(),
+            backend: Backend::Native,
+            sink: None,
+        }
+    }
+
+    pub fn id(mut self, id: &str) -> Builder<T> {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    pub fn title(mut self, title: &str) -> Builder<T> {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    pub fn category(mut self, category: IndicatorCategory) -> Builder<T> {
+        self.category = category;
+        self
+    }
+
+    pub fn icon(mut self, icon: IconResource) -> Builder<T> {
+        self.icon = Some(icon);
+        self
+    }
+
+    pub fn tooltip(mut self, tooltip: &str) -> Builder<T> {
+        self.tooltip = tooltip.to_string();
+        self
+    }
+
+    pub fn menu(mut self, menu: Menu<T>) -> Builder<T> {
+        self.menu = menu;
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Builder<T> {
+        self.backend = backend;
+        self
+    }
+
+    // Anything implementing EventSink: an mpsc Sender, a closure, or with the
+    // matching feature a crossbeam or futures channel.
+    pub fn event_sink<S: EventSink<T>>(mut self, sink: S) -> Builder<T> {
+        self.sink = Some(Box::new(sink));
+        self
+    }
+
+    pub fn build(self) -> Result<Application<T>, SystrayError> {
+        let id = self.id.unwrap_or_else(default_id);
+        let config = TrayConfig {
+            title: self.title.unwrap_or_else(|| id.clone()),
+            id: id,
+            category: self.category,
+            icon: self.icon.clone(),
+        };
+        // Without a sink events have nowhere to go, which is fine. That must
+        // not look like a sink that hung up though.
+        let sink = match self.sink {
+            Some(sink) => sink,
+            None => Box::new(|_: SystrayEvent<T>| {}) as Box<dyn EventSink<T>>,
+        };
+        let (backend_tx, backend_rx) = channel();
+        let window: Box<dyn BackendWindow> = match self.backend {
+            Backend::Native => Box::new(api::api::Window::new(backend_tx, &config)?),
+            #[cfg(target_os = "linux")]
+            Backend::GlibMainContext => Box::new(api::api::Window::attach(backend_tx, &config)?),
+            #[cfg(feature = "mock")]
+            Backend::Mock(tray) => Box::new(api::mock::Window::new(backend_tx, &config, tray)?),
+        };
+        let mut app = Application::from_window(window, backend_rx, sink);
+        app.set_tooltip(self.tooltip)?;
+        add_items(&mut app, None, self.menu.items)?;
+        // Icon last, it is what makes the tray show up.
+        if let Some(icon) = self.icon {
+            app.show_icon(icon)?;
+        }
+        Ok(app)
+    }
+}
+
+fn add_items<T>(app: &mut Application<T>, parent: Option<u32>, items: Vec<MenuItem<T>>) -> Result<(), SystrayError>
+    where T: Clone + Send + 'static {
+    for item in items {
+        match item {
+            MenuItem::Item(name, kind, enabled, f) => {
+                let idx = app.inner().add_menu_item_in(parent, &name, kind, enabled)?;
+                app.callback.insert(idx, f);
+            }
+            MenuItem::Payload(name, kind, enabled, payload) => {
+                let idx = app.inner().add_menu_item_in(parent, &name, kind, enabled)?;
+                app.payloads.lock().unwrap_or_else(|e| e.into_inner()).insert(idx, payload);
+            }
+            MenuItem::Submenu(name, enabled, menu) => {
+                let idx = app.inner().add_menu_item_in(parent, &name, MenuItemKind::Submenu, enabled)?;
+                add_items(app, Some(idx), menu.items)?;
+            }
+            MenuItem::Separator => {
+                app.add_menu_separator_in(parent)?;
+            }
+        }
+    }
+    Ok(())
+}
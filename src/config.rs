@@ -0,0 +1,441 @@
+// A whole tray described in a file, so labels and icons can change without a
+// rebuild. TOML, unless the file name ends in .json, .yaml or .yml:
+//
+//     id = "org.example.Backup"
+//     icon = "network-idle"
+//     tooltip = "Backup"
+//
+//     [[menu]]
+//     id = "run"
+//     label = "Run now"
+//
+//     [[menu]]
+//     id = "pause"
+//     label = "Paused"
+//     kind = "check"
+//     checked = true
+//
+//     [[menu]]
+//     kind = "separator"
+//
+//     [[menu]]
+//     label = "Schedule"
+//     submenu = [ { id = "daily", label = "Daily", kind = "radio", checked = true },
+//                 { id = "weekly", label = "Weekly", kind = "radio" },
+//                 { id = "never", label = "Never", kind = "radio", enabled = false } ]
+//
+// Item kinds are normal, check, radio, separator and submenu. Without one an
+// item is a submenu if it has one and normal otherwise, separator = true is
+// short for kind = "separator". Clickable items need an id, unique in the
+// whole file, which is what their clicks carry. Radio items are grouped by
+// the menu they are in, at most one of them checked.
+//
+// These become the same check, radio and submenu items a Menu has, building
+// fails with NotImplementedError on backends without them, see Capabilities.
+//
+// Application::apply_config brings a running tray in line with a changed
+// spec, the id and title only count when building it.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use serde_json;
+use serde_yaml;
+use toml;
+use {Application, Builder, IconResource, Inner, Menu, MenuItemKind, NewMenuEntry, Payloads, SystrayError};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TraySpec {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    // A file if one exists at that path, an icon theme or resource name
+    // otherwise.
+    pub icon: Option<String>,
+    pub tooltip: Option<String>,
+    #[serde(default)]
+    pub menu: Vec<ItemSpec>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ItemSpec {
+    pub id: Option<String>,
+    pub label: Option<String>,
+    pub kind: Option<ItemKind>,
+    #[serde(default)]
+    pub separator: bool,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub checked: bool,
+    #[serde(default)]
+    pub submenu: Vec<ItemSpec>,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemKind {
+    Normal,
+    Check,
+    Radio,
+    Separator,
+    Submenu,
+}
+
+impl ItemSpec {
+    pub fn kind(&self) -> ItemKind {
+        match self.kind {
+            Some(kind) => kind,
+            None if self.separator => ItemKind::Separator,
+            None if !self.submenu.is_empty() => ItemKind::Submenu,
+            None => ItemKind::Normal,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    // By file name extension, TOML for anything unknown.
+    pub fn of(path: &Path) -> Format {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Format::Json,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            _ => Format::Toml,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SpecError {
+    file: Option<String>,
+    line: Option<usize>,
+    message: String,
+}
+
+impl SpecError {
+    fn new(line: Option<usize>, message: String) -> SpecError {
+        SpecError {
+            file: None,
+            line: line,
+            message: message,
+        }
+    }
+
+    // Where the problem is, counting from 1, when that is known.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}:", file)?;
+            if let Some(line) = self.line {
+                write!(f, "{}:", line)?;
+            }
+            write!(f, " ")?;
+        } else if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Spec(SpecError),
+    Tray(SystrayError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Spec(ref e) => write!(f, "{}", e),
+            ConfigError::Tray(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<SpecError> for ConfigError {
+    fn from(e: SpecError) -> ConfigError {
+        ConfigError::Spec(e)
+    }
+}
+
+impl From<SystrayError> for ConfigError {
+    fn from(e: SystrayError) -> ConfigError {
+        ConfigError::Tray(e)
+    }
+}
+
+impl TraySpec {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<TraySpec, SpecError> {
+        let path = path.as_ref();
+        let with_file = |mut e: SpecError| {
+            e.file = Some(path.display().to_string());
+            e
+        };
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| with_file(SpecError::new(None, format!("cannot read it: {}", e))))?;
+        TraySpec::parse(&text, Format::of(path)).map_err(with_file)
+    }
+
+    pub fn parse(text: &str, format: Format) -> Result<TraySpec, SpecError> {
+        let spec: TraySpec = match format {
+            Format::Toml => toml::from_str(text)
+                .map_err(|e| SpecError::new(e.line_col().map(|(line, _)| line + 1), e.to_string()))?,
+            Format::Json => serde_json::from_str(text)
+                .map_err(|e| SpecError::new(Some(e.line()), e.to_string()))?,
+            Format::Yaml => serde_yaml::from_str(text)
+                .map_err(|e| SpecError::new(e.location().map(|l| l.line()), e.to_string()))?,
+        };
+        let mut ids = HashSet::new();
+        check_items(&spec.menu, &mut ids, text)?;
+        Ok(spec)
+    }
+
+    // Sets up builder, which picks the backend and event sink, and builds the
+    // tray from it. Clicks carry the item ids.
+    pub fn build(&self, builder: Builder<String>) -> Result<Application<String>, SystrayError> {
+        self.apply(builder).build()
+    }
+
+    // The menu the way Inner::update_menu takes it, each entry with the id
+    // its clicks carry.
+    fn entries(&self) -> Vec<(String, NewMenuEntry)> {
+        let mut entries = Vec::new();
+        add_entries(&mut entries, &self.menu, None);
+        entries
+    }
+
+    fn icon(&self) -> Option<IconResource> {
+        self.icon.as_ref().map(|icon| if Path::new(icon).is_file() {
+            IconResource::File(icon.clone())
+        } else {
+            IconResource::Resource(icon.clone())
+        })
+    }
+
+    fn apply(&self, mut builder: Builder<String>) -> Builder<String> {
+        if let Some(ref id) = self.id {
+            builder = builder.id(id);
+        }
+        if let Some(ref title) = self.title {
+            builder = builder.title(title);
+        }
+        if let Some(ref tooltip) = self.tooltip {
+            builder = builder.tooltip(tooltip);
+        }
+        if let Some(icon) = self.icon() {
+            builder = builder.icon(icon);
+        }
+        builder.menu(menu_of(&self.menu))
+    }
+}
+
+impl Application<String> {
+    // The tray described in the file at path, see systray::config.
+    pub fn from_config<P: AsRef<Path>>(path: P) -> Result<Application<String>, ConfigError> {
+        Application::from_config_with(path, Builder::new())
+    }
+
+    // Like from_config, with a builder that picks the backend and event sink.
+    pub fn from_config_with<P: AsRef<Path>>(path: P, builder: Builder<String>)
+                                            -> Result<Application<String>, ConfigError> {
+        let spec = TraySpec::load(&path)?;
+        let app = spec.build(builder)?;
+        // So enable_hot_reload knows what to watch.
+        #[cfg(all(target_os = "linux", feature = "hot-reload"))]
+        let app = {
+            let mut app = app;
+            app.spec_file = Some((path.as_ref().to_path_buf(), reload_spec));
+            app
+        };
+        Ok(app)
+    }
+
+    // Brings the tray in line with spec. Menu entries that are still the
+    // same kind of entry in the same place keep their index, the others are
+    // replaced, along with whatever the application added after them.
+    pub fn apply_config(&mut self, spec: &TraySpec) -> Result<(), SystrayError> {
+        let idxs = apply_spec(&mut self.inner(), &self.payloads, spec)?;
+        self.callback.retain(|idx, _| idxs.contains(idx));
+        Ok(())
+    }
+}
+
+// Returns the indexes of the menu entries.
+fn apply_spec(inner: &mut Inner, payloads: &Payloads<String>, spec: &TraySpec) -> Result<Vec<u32>, SystrayError> {
+    inner.set_tooltip(spec.tooltip.clone().unwrap_or_default())?;
+    if let Some(icon) = spec.icon() {
+        inner.show_icon(icon)?;
+    }
+    let (ids, entries): (Vec<String>, Vec<NewMenuEntry>) = spec.entries().into_iter().unzip();
+    let idxs = inner.update_menu(entries)?;
+    let mut payloads = payloads.lock().unwrap_or_else(|e| e.into_inner());
+    payloads.clear();
+    for (idx, id) in idxs.iter().zip(ids) {
+        if !id.is_empty() {
+            payloads.insert(*idx, id);
+        }
+    }
+    Ok(idxs)
+}
+
+#[cfg(all(target_os = "linux", feature = "hot-reload"))]
+fn reload_spec(inner: &mut Inner, payloads: &Payloads<String>, path: &Path) -> Result<(), String> {
+    let spec = TraySpec::load(path).map_err(|e| e.to_string())?;
+    apply_spec(inner, payloads, &spec)
+        .map(|_| ())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+// Line of the nth place key is set to value in text, as key = value, key:
+// value or "key": value, the value quoted or not. Comment lines are skipped.
+// The parsers don't give out positions, this is close enough to point people
+// at the item in any of the formats.
+fn key_line(text: &str, key: &str, value: &str, nth: usize) -> Option<usize> {
+    text.lines().enumerate().filter(|&(_, line)| {
+        !line.trim_start().starts_with('#') && line.match_indices(key).any(|(i, _)| {
+            if line[..i].chars().next_back().map_or(false, is_key_char) {
+                return false;
+            }
+            let rest = line[i + key.len()..].trim_start_matches(|c: char| c == '"' || c == '\'').trim_start();
+            if !rest.starts_with('=') && !rest.starts_with(':') {
+                return false;
+            }
+            let rest = rest[1..].trim_start();
+            let found = match rest.chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => rest[1..].split(quote).next(),
+                _ => rest.split(|c: char| c == ',' || c == '}' || c == '#').next().map(|v| v.trim_end()),
+            };
+            found == Some(value)
+        })
+    }).nth(nth).map(|(i, _)| i + 1)
+}
+
+fn check_items(items: &[ItemSpec], ids: &mut HashSet<String>, text: &str) -> Result<(), SpecError> {
+    let mut checked_radio = false;
+    for item in items {
+        let kind = item.kind();
+        let line = match (&item.id, &item.label) {
+            (&Some(ref id), _) => key_line(text, "id", id, 0),
+            (&None, &Some(ref label)) => key_line(text, "label", label, 0),
+            (&None, &None) => None,
+        };
+        let fail = |msg: String| Err(SpecError::new(line, msg));
+        if kind == ItemKind::Separator {
+            if item.id.is_some() || item.label.is_some() || !item.submenu.is_empty() {
+                return fail("a separator has no id, label or submenu".to_string());
+            }
+            continue;
+        }
+        if item.separator {
+            return fail(format!("a {:?} item can't be a separator", kind).to_lowercase());
+        }
+        let label = match item.label {
+            Some(ref label) => label,
+            None => return fail("menu item without a label".to_string()),
+        };
+        match kind {
+            ItemKind::Submenu if item.submenu.is_empty() => {
+                return fail(format!("submenu \"{}\" is empty", label));
+            }
+            ItemKind::Submenu => {}
+            _ if !item.submenu.is_empty() => {
+                return fail(format!("menu item \"{}\" can't have a submenu", label));
+            }
+            _ if item.id.is_none() => return fail(format!("menu item \"{}\" has no id", label)),
+            _ => {}
+        }
+        if item.checked {
+            match kind {
+                ItemKind::Check => {}
+                ItemKind::Radio if checked_radio => {
+                    return fail(format!("\"{}\" is the second checked radio item in its menu", label));
+                }
+                ItemKind::Radio => checked_radio = true,
+                _ => return fail(format!("menu item \"{}\" can't be checked", label)),
+            }
+        }
+        if let Some(ref id) = item.id {
+            if id.is_empty() {
+                return fail(format!("menu item \"{}\" has an empty id", label));
+            }
+            if !ids.insert(id.clone()) {
+                let line = key_line(text, "id", id, 1).or(line);
+                return Err(SpecError::new(line, format!("menu item id \"{}\" is used twice", id)));
+            }
+        }
+        check_items(&item.submenu, ids, text)?;
+    }
+    Ok(())
+}
+
+fn label(item: &ItemSpec) -> &str {
+    item.label.as_ref().map_or("", |label| &label[..])
+}
+
+fn item_kind(item: &ItemSpec) -> MenuItemKind {
+    match item.kind() {
+        ItemKind::Check => MenuItemKind::Check(item.checked),
+        ItemKind::Radio => MenuItemKind::Radio(item.checked),
+        ItemKind::Submenu => MenuItemKind::Submenu,
+        ItemKind::Normal | ItemKind::Separator => MenuItemKind::Plain,
+    }
+}
+
+fn menu_of(items: &[ItemSpec]) -> Menu<String> {
+    let mut menu = Menu::new();
+    for item in items {
+        let id = item.id.clone().unwrap_or_default();
+        menu = match item.kind() {
+            ItemKind::Separator => menu.separator(),
+            ItemKind::Normal => menu.item_with(label(item), id),
+            ItemKind::Check => menu.check_item_with(label(item), item.checked, id),
+            ItemKind::Radio => menu.radio_item_with(label(item), item.checked, id),
+            ItemKind::Submenu => menu.submenu(label(item), menu_of(&item.submenu)),
+        }.enabled(item.enabled);
+    }
+    menu
+}
+
+// Parent is the position of the submenu item in entries.
+fn add_entries(entries: &mut Vec<(String, NewMenuEntry)>, items: &[ItemSpec], parent: Option<usize>) {
+    for item in items {
+        if item.kind() == ItemKind::Separator {
+            entries.push((String::new(), NewMenuEntry::Separator(parent)));
+            continue;
+        }
+        let entry = NewMenuEntry::Item(label(item).to_string(), item.enabled, item_kind(item), parent);
+        entries.push((item.id.clone().unwrap_or_default(), entry));
+        if item.kind() == ItemKind::Submenu {
+            let pos = entries.len() - 1;
+            add_entries(entries, &item.submenu, Some(pos));
+        }
+    }
+}
@@ -0,0 +1,301 @@
+// Control socket for updating a running tray from other processes, cron jobs
+// and the like. The server listens on a Unix socket and takes the commands of
+// systray::protocol, one per line, on any number of connections. Every
+// command gets its reply lines followed by "ok", or a single "error <msg>"
+// line:
+//
+//     $ echo 'tooltip Backup running' | nc -U ~/.backup-tray.sock
+//     ok
+//
+// Anyone who can connect can change the tray, so the socket is only
+// accessible to its owner. It is bound inside a fresh directory of mode 0700
+// and linked into place once its own mode is down to 0600, so there is no
+// moment where others could connect. What that doesn't cover: processes of
+// the same user can always connect, whoever may write to the socket's
+// directory can remove or replace it, and the temporary directory next to
+// it needs that directory writable and a path a few bytes longer than the
+// socket's to fit in the socket address.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread::{self, JoinHandle};
+use libc;
+use protocol::{Command, CommandError, CommandRunner, error_line, error_message};
+use {Application, SystrayError};
+
+// Longer lines are rejected, so a client can't make the server buffer
+// without end.
+const MAX_LINE: usize = 64 * 1024;
+
+impl Application<String> {
+    // Listens for commands on a socket at path, which must not exist yet.
+    // Menu items are known by their payloads, as with Command::run. The
+    // server stops when the returned ControlServer is dropped.
+    pub fn serve_control<P: AsRef<Path>>(&self, path: P) -> Result<ControlServer, SystrayError> {
+        let path = path.as_ref().to_path_buf();
+        let os_error = |e: io::Error| SystrayError::OsError(format!("{}: {}", path.display(), e));
+        let listener = bind_private(&path).map_err(&os_error)?;
+        // Dropping the server closes stop, which wakes the accept loop.
+        let (stop, stopped) = UnixStream::pair()
+            .and_then(|pair| listener.set_nonblocking(true).map(|_| pair))
+            .map_err(&os_error)?;
+        let runner = self.command_runner();
+        let thread = thread::spawn(move || accept(runner, listener, stopped));
+        Ok(ControlServer {
+            path: path,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+// A connection being served, shut down to make its thread stop.
+struct Connection {
+    stream: UnixStream,
+    thread: JoinHandle<()>,
+}
+
+// Takes connections until stopped reads the end, then shuts down the ones
+// still open and waits for their threads.
+fn accept(runner: CommandRunner, listener: UnixListener, stopped: UnixStream) {
+    let (done_tx, done) = channel();
+    let mut connections = Vec::new();
+    let mut next_id = 0;
+    while let Ok(true) = wait(&listener, &stopped) {
+        forget_done(&mut connections, &done);
+        loop {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => continue,
+            };
+            let shutdown = match stream.set_nonblocking(false).and_then(|_| stream.try_clone()) {
+                Ok(shutdown) => shutdown,
+                Err(_) => continue,
+            };
+            let id = next_id;
+            next_id += 1;
+            let runner = runner.clone();
+            let done_tx = done_tx.clone();
+            let thread = thread::spawn(move || {
+                serve(runner, stream);
+                done_tx.send(id).ok();
+            });
+            connections.push((id, Connection {
+                stream: shutdown,
+                thread: thread,
+            }));
+        }
+    }
+    for (_, connection) in &connections {
+        connection.stream.shutdown(Shutdown::Both).ok();
+    }
+    for (_, connection) in connections {
+        connection.thread.join().ok();
+    }
+}
+
+// Drops the connections whose threads are done, their clients hung up.
+fn forget_done(connections: &mut Vec<(usize, Connection)>, done: &Receiver<usize>) {
+    for id in done.try_iter() {
+        if let Some(i) = connections.iter().position(|&(other, _)| other == id) {
+            let (_, connection) = connections.remove(i);
+            connection.thread.join().ok();
+        }
+    }
+}
+
+// Blocks until there is a connection to take or stopped has something to
+// read. False once the server is dropped.
+fn wait(listener: &UnixListener, stopped: &UnixStream) -> io::Result<bool> {
+    let mut fds = [
+        libc::pollfd { fd: listener.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+        libc::pollfd { fd: stopped.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+    ];
+    loop {
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } >= 0 {
+            break;
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    // Nothing is ever written, anything readable is the end.
+    Ok(fds[1].revents == 0)
+}
+
+// Binds in a private directory next to path, so nobody else can connect
+// before the mode is set, then links the socket to path. Linking fails
+// rather than replacing anything already there.
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file name")),
+    };
+    let dir = path.with_file_name(format!(".{}.{}", name, process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let tmp = dir.join("s");
+    let listener = UnixListener::bind(&tmp)
+        .and_then(|listener| {
+            fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+            fs::hard_link(&tmp, path)?;
+            Ok(listener)
+        });
+    fs::remove_file(&tmp).ok();
+    fs::remove_dir(&dir).ok();
+    listener
+}
+
+pub struct ControlServer {
+    path: PathBuf,
+    stop: Option<UnixStream>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ControlServer {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        // Closing our end wakes up the accept loop, which then stops the
+        // connections too.
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+// Reads the next line, None at the end of the stream. Lines longer than
+// MAX_LINE are skipped rather than kept.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<Result<String, CommandError>>> {
+    let mut buf = Vec::new();
+    if reader.by_ref().take(MAX_LINE as u64 + 1).read_until(b'\n', &mut buf)? == 0 {
+        return Ok(None);
+    }
+    if buf.last() != Some(&b'\n') && buf.len() > MAX_LINE {
+        loop {
+            buf.clear();
+            if reader.by_ref().take(MAX_LINE as u64).read_until(b'\n', &mut buf)? == 0 || buf.last() == Some(&b'\n') {
+                break;
+            }
+        }
+        return Ok(Some(Err(CommandError::BadCommand("line is too long".to_string()))));
+    }
+    while buf.last() == Some(&b'\n') || buf.last() == Some(&b'\r') {
+        buf.pop();
+    }
+    Ok(Some(String::from_utf8(buf)
+        .map_err(|_| CommandError::BadCommand("line is not UTF-8".to_string()))))
+}
+
+fn serve(runner: CommandRunner, stream: UnixStream) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    while let Ok(Some(line)) = read_line(&mut reader) {
+        if let Ok(ref line) = line {
+            if line.trim().is_empty() {
+                continue;
+            }
+        }
+        let reply = match line.and_then(|line| Command::parse(&line)).and_then(|command| runner.run(command)) {
+            Ok(mut lines) => {
+                lines.push("ok".to_string());
+                lines
+            }
+            Err(e) => vec![error_line(&e)],
+        };
+        let mut out = String::new();
+        for line in reply {
+            out.push_str(&line);
+            out.push('\n');
+        }
+        if writer.write_all(out.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ControlError {
+    Io(io::Error),
+    // The server's error message.
+    Remote(String),
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ControlError::Io(ref e) => write!(f, "{}", e),
+            ControlError::Remote(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for ControlError {
+    fn from(e: io::Error) -> ControlError {
+        ControlError::Io(e)
+    }
+}
+
+// Talks to a ControlServer, in this or another process.
+pub struct ControlClient {
+    writer: UnixStream,
+    reader: BufReader<UnixStream>,
+}
+
+impl ControlClient {
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<ControlClient, ControlError> {
+        let stream = UnixStream::connect(path)?;
+        Ok(ControlClient {
+            writer: stream.try_clone()?,
+            reader: BufReader::new(stream),
+        })
+    }
+
+    // Runs the command in the server, returns the lines it said back.
+    pub fn run(&mut self, command: &Command) -> Result<Vec<String>, ControlError> {
+        let line = command.to_string();
+        if line.contains('\n') {
+            return Err(ControlError::Remote("commands can't span lines".to_string()));
+        }
+        writeln!(self.writer, "{}", line)?;
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(ControlError::Io(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                           "server went away")));
+            }
+            let line = line.trim_end_matches(|c| c == '\n' || c == '\r');
+            if line == "ok" {
+                return Ok(lines);
+            }
+            if let Some(msg) = error_message(line) {
+                return Err(ControlError::Remote(msg));
+            }
+            lines.push(line.to_string());
+        }
+    }
+
+    // The lines of the state command.
+    pub fn state(&mut self) -> Result<Vec<String>, ControlError> {
+        self.run(&Command::State)
+    }
+}
@@ -0,0 +1,422 @@
+// The tray as a D-Bus object, so busctl and gdbus scripts and other desktop
+// programs can drive it. Application::serve_dbus exports an object at PATH
+// with this interface:
+//
+//     org.systrayrs.Tray1
+//         SetIcon(s icon)                  a file if one exists there, an icon name otherwise
+//         HideIcon()
+//         SetTooltip(s tooltip)
+//         SetLabel(s label)
+//         AddMenuItem(s id, s label)
+//         AddMenuSeparator()
+//         SetMenuItemEnabled(s id, b enabled)
+//         SetMenuItemLabel(s id, s label)
+//         Notify(s title, s body)
+//         SetState(s name)                 see systray::states
+//         Quit()
+//
+//         signal MenuItemClick(s id)
+//         signal LeftButtonClick()
+//         signal HostChanged()
+//         signal Reloaded(s file)          see Application::enable_hot_reload
+//         signal ReloadFailed(s message)
+//         signal Quit()                    the tray is gone, method calls fail from then on
+//
+//         property s Icon                  "" while there is none
+//         property b Visible
+//         property s Tooltip
+//         property s Label
+//         property a(sssbb) Menu           (kind, id, label, enabled, checked)
+//         property s State                 the tray state last applied, "" before any
+//
+// Properties are read only. Changes to them, through the bus or not, are
+// sent out with PropertiesChanged as they happen. Menu items
+// are known by their payloads, as with systray::protocol, whose commands the
+// methods run:
+//
+//     busctl --user call org.example.Backup /org/systrayrs/Tray1 \
+//         org.systrayrs.Tray1 SetTooltip s "Backup running"
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use std::collections::HashMap;
+use dbus::{self, Message, MethodErr};
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::blocking::stdintf::org_freedesktop_dbus::{PropertiesPropertiesChanged, RequestNameReply};
+use dbus::message::SignalArgs;
+use dbus::channel::{Channel, MatchingReceiver, Sender as DBusSender};
+use dbus::message::MatchRule;
+use dbus_crossroads::Crossroads;
+use libc;
+use protocol::{Command, CommandError, CommandRunner};
+use {Application, EventSink, IconResource, MenuEntryState, Payloads, SystrayError, SystrayEvent,
+     TrayHandle};
+
+pub const INTERFACE: &'static str = "org.systrayrs.Tray1";
+pub const PATH: &'static str = "/org/systrayrs/Tray1";
+
+impl Application<String> {
+    // Exports the tray on the session bus under the well-known name, which
+    // must be free. The object goes away when the returned DBusService is
+    // dropped. Signals go out for the events the application's sink gets,
+    // also after events() swapped that sink.
+    pub fn serve_dbus(&self, name: &str) -> Result<DBusService, SystrayError> {
+        self.serve_dbus_with(name, Connection::new_session)
+    }
+
+    // Like serve_dbus, on the bus at the given address rather than the
+    // session bus.
+    pub fn serve_dbus_at(&self, address: &str, name: &str) -> Result<DBusService, SystrayError> {
+        let address = address.to_string();
+        self.serve_dbus_with(name, move || {
+            let mut channel = Channel::open_private(&address)?;
+            channel.register()?;
+            Ok(Connection::from(channel))
+        })
+    }
+
+    fn serve_dbus_with<F>(&self, name: &str, connect: F) -> Result<DBusService, SystrayError>
+        where F: FnOnce() -> Result<Connection, dbus::Error> + Send + 'static {
+        let tray = Tray {
+            runner: self.command_runner(),
+            handle: self.handle(),
+            payloads: self.payloads.clone(),
+        };
+        let (updates_tx, updates) = channel();
+        let (wake, woken) = UnixStream::pair()
+            .and_then(|(wake, woken)| wake.set_nonblocking(true).map(|_| (wake, woken)))
+            .map_err(|e| SystrayError::OsError(format!("D-Bus: {}", e)))?;
+        let wake = Wake {
+            updates: updates_tx,
+            wake: Arc::new(wake),
+        };
+        let (ready_tx, ready_rx) = channel();
+        let name = name.to_string();
+        let thread = thread::spawn(move || {
+            let conn = match connect().and_then(|conn| {
+                let reply = conn.request_name(&name[..], false, false, true)?;
+                Ok((conn, reply))
+            }) {
+                Ok((conn, RequestNameReply::PrimaryOwner)) => conn,
+                Ok(_) => {
+                    ready_tx.send(Err(format!("the name {} is taken", name))).ok();
+                    return;
+                }
+                Err(e) => {
+                    ready_tx.send(Err(e.to_string())).ok();
+                    return;
+                }
+            };
+            ready_tx.send(Ok(())).ok();
+            serve(conn, tray, updates, woken);
+        });
+        match ready_rx.recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => return Err(SystrayError::OsError(format!("D-Bus: {}", e))),
+            Err(_) => return Err(SystrayError::OsError("D-Bus service thread died".to_string())),
+        }
+        // Events keep going to the sink the application has, the service
+        // gets copies. Whatever changes the tray tells it so as well.
+        let changes = wake.clone();
+        self.inner().observers.push(Box::new(move || changes.send(Update::Changed)));
+        self.sink.lock().unwrap_or_else(|e| e.into_inner()).copies.push(Box::new(wake.clone()));
+        Ok(DBusService {
+            wake: wake,
+            thread: Some(thread),
+        })
+    }
+}
+
+pub struct DBusService {
+    wake: Wake,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for DBusService {
+    fn drop(&mut self) {
+        self.wake.send(Update::Stop);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+// What the service thread is told about.
+enum Update {
+    Event(SystrayEvent<String>),
+    Changed,
+    Stop,
+}
+
+// Hands the service thread an update and wakes it up, it could be blocked
+// on the bus.
+#[derive(Clone)]
+struct Wake {
+    updates: Sender<Update>,
+    wake: Arc<UnixStream>,
+}
+
+impl Wake {
+    // False once the service thread is gone.
+    fn send(&self, update: Update) -> bool {
+        if self.updates.send(update).is_err() {
+            return false;
+        }
+        // A full socket has a wake-up waiting already.
+        (&*self.wake).write_all(&[0]).ok();
+        true
+    }
+}
+
+impl EventSink<String> for Wake {
+    fn send_event(&mut self, event: SystrayEvent<String>) -> Result<(), SystrayError> {
+        if self.send(Update::Event(event)) {
+            Ok(())
+        } else {
+            Err(SystrayError::EventSinkClosed)
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Tray {
+    runner: CommandRunner,
+    handle: TrayHandle,
+    payloads: Payloads<String>,
+}
+
+impl Tray {
+    fn run(&self, command: Command) -> Result<(), MethodErr> {
+        self.runner.run(command).map(|_| ()).map_err(|e| {
+            let name = match e {
+                CommandError::UnknownId(_) => "org.systrayrs.Tray1.Error.UnknownId",
+                CommandError::DuplicateId(_) => "org.systrayrs.Tray1.Error.DuplicateId",
+                CommandError::BadCommand(_) => "org.freedesktop.DBus.Error.InvalidArgs",
+                CommandError::Tray(_) => "org.systrayrs.Tray1.Error.Tray",
+            };
+            (name, e.to_string()).into()
+        })
+    }
+
+    fn properties(&self) -> Result<Properties, SystrayError> {
+        let state = self.handle.state().wait()?;
+        let payloads = self.payloads.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(Properties {
+            icon: match state.icon {
+                Some(IconResource::File(icon)) | Some(IconResource::Resource(icon)) => icon,
+                None => "".to_string(),
+            },
+            visible: state.visible,
+            tooltip: state.tooltip,
+            label: state.label,
+            menu: {
+                let mut menu = Vec::new();
+                menu_entries(&mut menu, state.menu, &payloads);
+                menu
+            },
+            state: state.current_state.unwrap_or_default(),
+        })
+    }
+}
+
+// Kinds are "item", "check", "radio", "separator" and "submenu", whose
+// entries follow up to an "end".
+fn menu_entries(menu: &mut Vec<(String, String, String, bool, bool)>, entries: Vec<MenuEntryState>,
+                payloads: &HashMap<u32, String>) {
+    let id = |idx: u32| payloads.get(&idx).cloned().unwrap_or_default();
+    for entry in entries {
+        match entry {
+            MenuEntryState::Item(idx, label, enabled) => menu.push(("item".to_string(), id(idx), label, enabled, false)),
+            MenuEntryState::Check(idx, label, enabled, checked) => {
+                menu.push(("check".to_string(), id(idx), label, enabled, checked))
+            }
+            MenuEntryState::Radio(idx, label, enabled, checked) => {
+                menu.push(("radio".to_string(), id(idx), label, enabled, checked))
+            }
+            MenuEntryState::Submenu(idx, label, enabled, entries) => {
+                menu.push(("submenu".to_string(), id(idx), label, enabled, false));
+                menu_entries(menu, entries, payloads);
+                menu.push(("end".to_string(), "".to_string(), "".to_string(), false, false));
+            }
+            MenuEntryState::Separator(_) => {
+                menu.push(("separator".to_string(), "".to_string(), "".to_string(), false, false))
+            }
+        }
+    }
+}
+
+// The tray as the properties show it.
+struct Properties {
+    icon: String,
+    visible: bool,
+    tooltip: String,
+    label: String,
+    menu: Vec<(String, String, String, bool, bool)>,
+    state: String,
+}
+
+impl Properties {
+    // Those that differ from old, under their property names.
+    fn changed(&self, old: &Properties) -> PropMap {
+        let mut changed: PropMap = HashMap::new();
+        {
+            let mut check = |name: &str, differs: bool, value: Box<dyn RefArg>| if differs {
+                changed.insert(name.to_string(), Variant(value));
+            };
+            check("Icon", self.icon != old.icon, Box::new(self.icon.clone()));
+            check("Visible", self.visible != old.visible, Box::new(self.visible));
+            check("Tooltip", self.tooltip != old.tooltip, Box::new(self.tooltip.clone()));
+            check("Label", self.label != old.label, Box::new(self.label.clone()));
+            check("Menu", self.menu != old.menu, Box::new(self.menu.clone()));
+            check("State", self.state != old.state, Box::new(self.state.clone()));
+        }
+        changed
+    }
+}
+
+fn property<R, F>(tray: &Tray, f: F) -> Result<R, MethodErr>
+    where F: FnOnce(Properties) -> R {
+    tray.properties().map(f).map_err(|e| MethodErr::failed(&e))
+}
+
+fn serve(conn: Connection, tray: Tray, updates: Receiver<Update>, woken: UnixStream) {
+    let mut cr = Crossroads::new();
+    let iface = cr.register(INTERFACE, |b| {
+        b.method("SetIcon", ("icon",), (), |_, tray: &mut Tray, (icon,): (String,)| {
+            tray.run(Command::Icon(icon))
+        });
+        b.method("HideIcon", (), (), |_, tray: &mut Tray, (): ()| tray.run(Command::Hide));
+        b.method("SetTooltip", ("tooltip",), (), |_, tray: &mut Tray, (tooltip,): (String,)| {
+            tray.run(Command::Tooltip(tooltip))
+        });
+        b.method("SetLabel", ("label",), (), |_, tray: &mut Tray, (label,): (String,)| {
+            tray.run(Command::Label(label))
+        });
+        b.method("AddMenuItem", ("id", "label"), (), |_, tray: &mut Tray, (id, label): (String, String)| {
+            tray.run(Command::AddItem(id, label))
+        });
+        b.method("AddMenuSeparator", (), (), |_, tray: &mut Tray, (): ()| tray.run(Command::Separator));
+        b.method("SetMenuItemEnabled", ("id", "enabled"), (), |_, tray: &mut Tray, (id, enabled): (String, bool)| {
+            tray.run(Command::SetEnabled(id, enabled))
+        });
+        b.method("SetMenuItemLabel", ("id", "label"), (), |_, tray: &mut Tray, (id, label): (String, String)| {
+            tray.run(Command::SetItemLabel(id, label))
+        });
+        b.method("Notify", ("title", "body"), (), |_, tray: &mut Tray, (title, body): (String, String)| {
+            tray.run(Command::Notify(title, body))
+        });
+        b.method("SetState", ("name",), (), |_, tray: &mut Tray, (name,): (String,)| {
+            tray.run(Command::SetState(name))
+        });
+        b.method("Quit", (), (), |_, tray: &mut Tray, (): ()| tray.run(Command::Quit));
+
+        b.signal::<(String,), _>("MenuItemClick", ("id",));
+        b.signal::<(), _>("LeftButtonClick", ());
+        b.signal::<(), _>("HostChanged", ());
+        b.signal::<(String,), _>("Reloaded", ("file",));
+        b.signal::<(String,), _>("ReloadFailed", ("message",));
+        b.signal::<(), _>("Quit", ());
+
+        b.property("Icon").emits_changed_true().get(|_, tray: &mut Tray| property(tray, |p| p.icon));
+        b.property("Visible").emits_changed_true().get(|_, tray: &mut Tray| property(tray, |p| p.visible));
+        b.property("Tooltip").emits_changed_true().get(|_, tray: &mut Tray| property(tray, |p| p.tooltip));
+        b.property("Label").emits_changed_true().get(|_, tray: &mut Tray| property(tray, |p| p.label));
+        b.property("Menu").emits_changed_true().get(|_, tray: &mut Tray| property(tray, |p| p.menu));
+        b.property("State").emits_changed_true().get(|_, tray: &mut Tray| property(tray, |p| p.state));
+    });
+    // The application can change the tray without going through the bus,
+    // so changes are found by comparing with what was last seen whenever it
+    // says something changed.
+    let watched = tray.clone();
+    let mut seen = watched.properties().ok();
+    cr.insert(PATH, &[iface], tray);
+    conn.start_receive(MatchRule::new_method_call(), Box::new(move |msg, conn| {
+        // Only fails on messages that aren't method calls, which the rule
+        // keeps out.
+        cr.handle_message(msg, conn).ok();
+        true
+    }));
+    loop {
+        loop {
+            match conn.process(Duration::from_millis(0)) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(_) => return,
+            }
+        }
+        let mut changed = false;
+        for update in updates.try_iter() {
+            let signal = |name: &str| Message::new_signal(PATH, INTERFACE, name).unwrap();
+            let msg = match update {
+                Update::Event(SystrayEvent::MenuItemClick(id)) => signal("MenuItemClick").append1(id),
+                Update::Event(SystrayEvent::LeftButtonClick) => signal("LeftButtonClick"),
+                Update::Event(SystrayEvent::HostChanged) => signal("HostChanged"),
+                Update::Event(SystrayEvent::Reloaded(file)) => signal("Reloaded").append1(file),
+                Update::Event(SystrayEvent::ReloadFailed(msg)) => signal("ReloadFailed").append1(msg),
+                Update::Event(SystrayEvent::Quit) => signal("Quit"),
+                Update::Changed => {
+                    changed = true;
+                    continue;
+                }
+                Update::Stop => return,
+            };
+            conn.send(msg).ok();
+        }
+        // Fails once the application is dropped, taking the worker that
+        // answers state() with it.
+        if changed {
+            if let Ok(now) = watched.properties() {
+                let changed = seen.as_ref().map(|old| now.changed(old)).unwrap_or_default();
+                if !changed.is_empty() {
+                    let signal = PropertiesPropertiesChanged {
+                        interface_name: INTERFACE.to_string(),
+                        changed_properties: changed,
+                        invalidated_properties: Vec::new(),
+                    };
+                    conn.send(signal.to_emit_message(&PATH.into())).ok();
+                }
+                seen = Some(now);
+            }
+        }
+        conn.channel().flush();
+        match wait(&conn, &woken) {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                warn!("D-Bus service stopped: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+// Blocks until the bus has something for us or we were woken. False once
+// everyone that could wake us is gone.
+fn wait(conn: &Connection, woken: &UnixStream) -> io::Result<bool> {
+    let mut fds = [
+        libc::pollfd { fd: conn.channel().watch().fd, events: libc::POLLIN, revents: 0 },
+        libc::pollfd { fd: woken.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+    ];
+    loop {
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } >= 0 {
+            break;
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    if fds[1].revents != 0 {
+        let mut buffer = [0; 64];
+        if (&*woken).read(&mut buffer)? == 0 {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,286 @@
+// Send + Sync way of driving the tray from other threads. Commands are run
+// one at a time on a worker thread that owns the core state, the backend then
+// takes care of getting them onto its own thread.
+
+use std::cell::Cell;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+use {Application, Inner, IconResource, SystrayError, TrayState, lock_inner};
+
+#[cfg(not(feature = "async"))]
+use std::sync::mpsc::{channel as reply_channel, Receiver as ReplyReceiver};
+#[cfg(feature = "async")]
+use futures::sync::oneshot::{channel as reply_channel, Receiver as ReplyReceiver};
+#[cfg(feature = "async")]
+use futures::{Async, Future, Poll};
+
+pub type Job = Box<dyn FnOnce(&mut Inner) + Send>;
+
+pub fn spawn_worker(inner: Arc<Mutex<Inner>>) -> Sender<Job> {
+    let (tx, rx) = channel::<Job>();
+    thread::spawn(move || {
+        for job in rx.iter() {
+            job(&mut lock_inner(&inner));
+        }
+    });
+    tx
+}
+
+// Set while a timer callback runs. The backend loop running it may be the
+// one that has to answer a command, so waiting for one there would hang.
+thread_local!(static IN_TIMER: Cell<bool> = Cell::new(false));
+
+fn check_not_in_timer() -> Result<(), SystrayError> {
+    if IN_TIMER.with(|in_timer| in_timer.get()) {
+        return Err(SystrayError::WaitInTimer);
+    }
+    Ok(())
+}
+
+// Outcome of a command sent through a TrayHandle. Dropping it lets the command
+// run in the background, wait() blocks until it is done. With the async
+// feature it is also a Future. A handle taken into a timer callback can't
+// wait(), it fails with WaitInTimer there, the command still runs. Timer
+// callbacks get a TimerTray instead, see Application::add_timeout.
+pub struct Reply<T> {
+    rx: ReplyReceiver<Result<T, SystrayError>>,
+}
+
+impl<T> Reply<T> {
+    #[cfg(not(feature = "async"))]
+    pub fn wait(self) -> Result<T, SystrayError> {
+        check_not_in_timer()?;
+        match self.rx.recv() {
+            Ok(r) => r,
+            Err(_) => Err(SystrayError::BackendGone),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    pub fn wait(self) -> Result<T, SystrayError> {
+        check_not_in_timer()?;
+        Future::wait(self)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Future for Reply<T> {
+    type Item = T;
+    type Error = SystrayError;
+
+    fn poll(&mut self) -> Poll<T, SystrayError> {
+        match self.rx.poll() {
+            Ok(Async::Ready(r)) => r.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => Err(SystrayError::BackendGone),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TrayHandle {
+    jobs: Sender<Job>,
+}
+
+impl<T> Application<T> {
+    // Cloneable handle for updating the tray from any thread.
+    pub fn handle(&self) -> TrayHandle {
+        TrayHandle {
+            jobs: self.jobs.clone()
+        }
+    }
+
+    // Runs f once on the backend loop after the given time. The application
+    // stays on the thread it was built on, so f gets a TimerTray. Its
+    // commands are queued without a reply, the backend loop running f may
+    // be the one that has to carry them out.
+    pub fn add_timeout<F>(&mut self, after: Duration, f: F) -> Result<TimerHandle, SystrayError>
+        where F: FnOnce(&TimerTray) + Send + 'static {
+        let mut f = Some(f);
+        self.add_timer(after, move |tray| {
+            if let Some(f) = f.take() {
+                f(tray);
+            }
+            false
+        })
+    }
+
+    // Like add_timeout, but runs f every interval until cancelled.
+    pub fn add_interval<F>(&mut self, every: Duration, mut f: F) -> Result<TimerHandle, SystrayError>
+        where F: FnMut(&TimerTray) + Send + 'static {
+        self.add_timer(every, move |tray| {
+            f(tray);
+            true
+        })
+    }
+
+    fn add_timer<F>(&mut self, interval: Duration, mut f: F) -> Result<TimerHandle, SystrayError>
+        where F: FnMut(&TimerTray) -> bool + Send + 'static {
+        let tray = TimerTray {
+            tray: self.handle()
+        };
+        let tick = move || {
+            let outer = IN_TIMER.with(|in_timer| in_timer.replace(true));
+            let keep = f(&tray);
+            IN_TIMER.with(|in_timer| in_timer.set(outer));
+            keep
+        };
+        let timer_id = lock_inner(&self.inner).add_timer(interval, Box::new(tick))?;
+        Ok(TimerHandle {
+            timer_id: timer_id,
+            tray: self.handle()
+        })
+    }
+}
+
+impl TrayHandle {
+    // Runs f on the worker, nothing else gets at the tray meanwhile.
+    pub(crate) fn run<T, F>(&self, f: F) -> Reply<T>
+        where T: Send + 'static,
+              F: FnOnce(&mut Inner) -> Result<T, SystrayError> + Send + 'static {
+        let (tx, rx) = reply_channel();
+        let job: Job = Box::new(move |inner: &mut Inner| {
+            tx.send(f(inner)).ok();
+        });
+        // If the worker is gone the job, and with it the reply sender, is
+        // dropped here and wait() reports the failure.
+        let _ = self.jobs.send(job);
+        Reply {
+            rx: rx
+        }
+    }
+
+    pub fn show_icon(&self, icon: IconResource) -> Reply<()> {
+        self.run(move |inner| inner.show_icon(icon))
+    }
+
+    pub fn hide_icon(&self) -> Reply<()> {
+        self.run(|inner| inner.hide_icon())
+    }
+
+    pub fn set_tooltip(&self, tooltip: String) -> Reply<()> {
+        self.run(move |inner| inner.set_tooltip(tooltip))
+    }
+
+    pub fn set_label(&self, label: Option<String>, guide: Option<String>) -> Reply<()> {
+        self.run(move |inner| inner.set_label(label, guide))
+    }
+
+    pub fn add_menu_item(&self, item_name: String) -> Reply<u32> {
+        self.run(move |inner| inner.add_menu_item(&item_name))
+    }
+
+    pub fn add_menu_separator(&self) -> Reply<u32> {
+        self.run(|inner| inner.add_menu_separator())
+    }
+
+    pub fn set_menu_item_label(&self, item_idx: u32, item_name: String) -> Reply<()> {
+        self.run(move |inner| inner.set_menu_item_label(item_idx, &item_name))
+    }
+
+    pub fn set_menu_item_enabled(&self, item_idx: u32, enabled: bool) -> Reply<()> {
+        self.run(move |inner| inner.set_menu_item_enabled(item_idx, enabled))
+    }
+
+    pub fn set_menu_item_checked(&self, item_idx: u32, checked: bool) -> Reply<()> {
+        self.run(move |inner| inner.set_menu_item_checked(item_idx, checked))
+    }
+
+    pub fn notify(&self, title: String, body: String) -> Reply<()> {
+        self.run(move |inner| inner.notify(&title, &body))
+    }
+
+    // See Application::set_state.
+    pub fn set_state(&self, name: String) -> Reply<()> {
+        self.run(move |inner| inner.set_state(&name))
+    }
+
+    pub fn state(&self) -> Reply<TrayState> {
+        self.run(|inner| Ok(inner.state()))
+    }
+
+    pub fn quit(&self) -> Reply<()> {
+        self.run(|inner| {
+            inner.quit();
+            Ok(())
+        })
+    }
+}
+
+// What timer callbacks get: the TrayHandle commands, without replies. They
+// are queued like any other command and failures are logged.
+pub struct TimerTray {
+    tray: TrayHandle,
+}
+
+impl TimerTray {
+    fn send<F>(&self, what: &'static str, f: F)
+        where F: FnOnce(&mut Inner) -> Result<(), SystrayError> + Send + 'static {
+        let job: Job = Box::new(move |inner: &mut Inner| {
+            if let Err(e) = f(inner) {
+                warn!("{} from a timer failed: {}", what, e);
+            }
+        });
+        let _ = self.tray.jobs.send(job);
+    }
+
+    pub fn show_icon(&self, icon: IconResource) {
+        self.send("show_icon", move |inner| inner.show_icon(icon))
+    }
+
+    pub fn hide_icon(&self) {
+        self.send("hide_icon", |inner| inner.hide_icon())
+    }
+
+    pub fn set_tooltip(&self, tooltip: String) {
+        self.send("set_tooltip", move |inner| inner.set_tooltip(tooltip))
+    }
+
+    pub fn set_label(&self, label: Option<String>, guide: Option<String>) {
+        self.send("set_label", move |inner| inner.set_label(label, guide))
+    }
+
+    pub fn set_menu_item_label(&self, item_idx: u32, item_name: String) {
+        self.send("set_menu_item_label", move |inner| inner.set_menu_item_label(item_idx, &item_name))
+    }
+
+    pub fn set_menu_item_enabled(&self, item_idx: u32, enabled: bool) {
+        self.send("set_menu_item_enabled", move |inner| inner.set_menu_item_enabled(item_idx, enabled))
+    }
+
+    pub fn set_menu_item_checked(&self, item_idx: u32, checked: bool) {
+        self.send("set_menu_item_checked", move |inner| inner.set_menu_item_checked(item_idx, checked))
+    }
+
+    pub fn notify(&self, title: String, body: String) {
+        self.send("notify", move |inner| inner.notify(&title, &body))
+    }
+
+    pub fn set_state(&self, name: String) {
+        self.send("set_state", move |inner| inner.set_state(&name))
+    }
+
+    pub fn quit(&self) {
+        self.send("quit", |inner| {
+            inner.quit();
+            Ok(())
+        })
+    }
+}
+
+// Returned by Application::add_timeout and add_interval.
+#[derive(Clone)]
+pub struct TimerHandle {
+    timer_id: u32,
+    tray: TrayHandle,
+}
+
+impl TimerHandle {
+    // Stops the timer. A timer that is done already is left alone.
+    pub fn cancel(&self) -> Reply<()> {
+        let timer_id = self.timer_id;
+        self.tray.run(move |inner| inner.window.remove_timer(timer_id))
+    }
+}
@@ -0,0 +1,378 @@
+// JSON-RPC 2.0 over lines of text, so programs in any language can run the
+// systray tool as a helper process (systray --json-rpc) and get a tray. Every
+// line is one JSON object, in both directions.
+//
+// The client starts with
+//
+//     {"jsonrpc": "2.0", "id": 1, "method": "hello", "params": {"version": 1}}
+//
+// and gets back the protocol version and what the backend can do:
+//
+//     {"jsonrpc": "2.0", "id": 1, "result": {"version": 1, "server": "systray-rs 0.2.0",
+//      "capabilities": {"left_click": true, ...}}}
+//
+// Nothing else is accepted before that. Methods and their params:
+//
+//     capabilities
+//     show_icon             {"file": path} or {"resource": name}
+//     hide_icon
+//     set_tooltip           {"tooltip": text}
+//     set_label             {"label": text, "guide": text}, both optional
+//     add_menu_item         {"id": id, "label": text}
+//     add_menu_separator
+//     set_menu_item_label   {"id": id, "label": text}
+//     set_menu_item_enabled {"id": id, "enabled": bool}
+//     notify                {"title": text, "body": text}
+//     add_timeout           {"id": id, "ms": number}
+//     add_interval          {"id": id, "ms": number}
+//     cancel_timer          {"id": id}
+//     set_state             {"name": name}
+//     state
+//     quit
+//
+// set_state switches to a tray state the application set up, see
+// systray::states, the systray tool sets up none. state answers with what
+// the tray shows:
+//
+//     {"icon": {"file": path} or {"resource": name} or null, "visible": bool,
+//      "tooltip": text, "label": text, "guide": text,
+//      "menu": [{"type": "item", "id": id, "label": text, "enabled": bool},
+//               {"type": "check" or "radio", ..., "checked": bool},
+//               {"type": "submenu", ..., "entries": [...]},
+//               {"type": "separator"}],
+//      "current_state": name or null}
+//
+// Not covered: icons from a buffer, which only the Windows backend has and
+// Application doesn't, setting up tray states, menus from config files and
+// their reloading, and the backend timeout.
+//
+// Results are null unless the method asks for something. Requests without an
+// id get no response, failed ones included. Lines that aren't a request at
+// all, batches among them, get an error with a null id. The server sends
+// notifications of its own:
+//
+//     event {"type": "menu_item_click", "id": id}
+//     event {"type": "left_button_click"}
+//     event {"type": "host_changed"}
+//     event {"type": "reloaded", "file": file}
+//     event {"type": "reload_failed", "message": message}
+//     event {"type": "quit"}             the last line there is
+//     timer {"id": id}
+//
+// Menu item and timer ids are strings the client picks. A new timer replaces
+// any older one with its id.
+
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use serde_json::{self, Value};
+use {Application, Capabilities, IconResource, MenuEntryState, SystrayError, SystrayEvent, TimerHandle};
+
+// Bumped on changes old clients would trip over.
+pub const PROTOCOL_VERSION: u64 = 1;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// Codes of our own.
+const TRAY_ERROR: i64 = -32000;
+const NO_HELLO: i64 = -32001;
+const BAD_VERSION: i64 = -32002;
+
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: String) -> RpcError {
+        RpcError {
+            code: code,
+            message: message,
+            data: None,
+        }
+    }
+}
+
+impl From<SystrayError> for RpcError {
+    fn from(e: SystrayError) -> RpcError {
+        RpcError::new(TRAY_ERROR, e.to_string())
+    }
+}
+
+fn invalid_params(msg: &str) -> RpcError {
+    RpcError::new(INVALID_PARAMS, msg.to_string())
+}
+
+fn notification(method: &str, params: Value) -> String {
+    json!({"jsonrpc": "2.0", "method": method, "params": params}).to_string()
+}
+
+fn capabilities_json(caps: &Capabilities) -> Value {
+    json!({
+        "left_click": caps.left_click,
+        "tooltip": caps.tooltip,
+        "icon_from_buffer": caps.icon_from_buffer,
+        "submenus": caps.submenus,
+        "check_items": caps.check_items,
+        "notifications": caps.notifications,
+        "attention": caps.attention,
+        "hide_show": caps.hide_show,
+        "label": caps.label,
+    })
+}
+
+// The line a tray event is passed on to the client as.
+pub fn event_notification(event: &SystrayEvent<String>) -> String {
+    let params = match *event {
+        SystrayEvent::MenuItemClick(ref id) => json!({"type": "menu_item_click", "id": id}),
+        SystrayEvent::LeftButtonClick => json!({"type": "left_button_click"}),
+        SystrayEvent::HostChanged => json!({"type": "host_changed"}),
+        SystrayEvent::Reloaded(ref file) => json!({"type": "reloaded", "file": file}),
+        SystrayEvent::ReloadFailed(ref msg) => json!({"type": "reload_failed", "message": msg}),
+        SystrayEvent::Quit => json!({"type": "quit"}),
+    };
+    notification("event", params)
+}
+
+struct Params<'a>(Option<&'a Value>);
+
+impl<'a> Params<'a> {
+    fn get(&self, name: &str) -> Option<&'a Value> {
+        self.0.and_then(|p| p.get(name)).and_then(|v| if v.is_null() { None } else { Some(v) })
+    }
+
+    fn string(&self, name: &str) -> Result<String, RpcError> {
+        self.opt_string(name)?
+            .ok_or_else(|| invalid_params(&format!("\"{}\" is missing", name)))
+    }
+
+    fn opt_string(&self, name: &str) -> Result<Option<String>, RpcError> {
+        match self.get(name) {
+            Some(v) => v.as_str()
+                .map(|s| Some(s.to_string()))
+                .ok_or_else(|| invalid_params(&format!("\"{}\" must be a string", name))),
+            None => Ok(None),
+        }
+    }
+
+    fn bool(&self, name: &str) -> Result<bool, RpcError> {
+        self.get(name).and_then(|v| v.as_bool())
+            .ok_or_else(|| invalid_params(&format!("\"{}\" must be true or false", name)))
+    }
+
+    fn u64(&self, name: &str) -> Result<u64, RpcError> {
+        self.get(name).and_then(|v| v.as_u64())
+            .ok_or_else(|| invalid_params(&format!("\"{}\" must be a whole number", name)))
+    }
+}
+
+// The method of a well-formed request.
+fn request_method(request: &Value) -> Result<&str, RpcError> {
+    if request.is_array() {
+        return Err(RpcError::new(INVALID_REQUEST, "batches are not supported".to_string()));
+    }
+    if !request.is_object() {
+        return Err(RpcError::new(INVALID_REQUEST, "a request must be an object".to_string()));
+    }
+    if request.get("jsonrpc").and_then(|v| v.as_str()) != Some("2.0") {
+        return Err(RpcError::new(INVALID_REQUEST, "not a JSON-RPC 2.0 request".to_string()));
+    }
+    match request.get("id") {
+        Some(id) if !(id.is_string() || id.is_number() || id.is_null()) => {
+            return Err(RpcError::new(INVALID_REQUEST, "id must be a string or a number".to_string()));
+        }
+        _ => {}
+    }
+    request.get("method").and_then(|v| v.as_str())
+        .ok_or_else(|| RpcError::new(INVALID_REQUEST, "no method".to_string()))
+}
+
+fn menu_json(menu: &[MenuEntryState], payloads: &HashMap<u32, String>) -> Vec<Value> {
+    menu.iter().map(|entry| match *entry {
+        MenuEntryState::Item(idx, ref label, enabled) => {
+            json!({"type": "item", "id": payloads.get(&idx), "label": label, "enabled": enabled})
+        }
+        MenuEntryState::Check(idx, ref label, enabled, checked) => {
+            json!({"type": "check", "id": payloads.get(&idx), "label": label, "enabled": enabled,
+                   "checked": checked})
+        }
+        MenuEntryState::Radio(idx, ref label, enabled, checked) => {
+            json!({"type": "radio", "id": payloads.get(&idx), "label": label, "enabled": enabled,
+                   "checked": checked})
+        }
+        MenuEntryState::Submenu(idx, ref label, enabled, ref entries) => {
+            json!({"type": "submenu", "id": payloads.get(&idx), "label": label, "enabled": enabled,
+                   "entries": menu_json(entries, payloads)})
+        }
+        MenuEntryState::Separator(_) => json!({"type": "separator"}),
+    }).collect()
+}
+
+fn state_json(app: &Application<String>) -> Value {
+    let state = app.state();
+    let icon = match state.icon {
+        Some(IconResource::File(ref file)) => json!({"file": file}),
+        Some(IconResource::Resource(ref name)) => json!({"resource": name}),
+        None => Value::Null,
+    };
+    let payloads = app.payloads.lock().unwrap_or_else(|e| e.into_inner());
+    let menu = menu_json(&state.menu, &payloads);
+    json!({
+        "icon": icon,
+        "visible": state.visible,
+        "tooltip": state.tooltip,
+        "label": state.label,
+        "guide": state.label_guide,
+        "menu": menu,
+        "current_state": state.current_state,
+    })
+}
+
+// One client's session with a tray whose menu item payloads are the ids.
+pub struct JsonRpc {
+    greeted: bool,
+    timers: HashMap<String, TimerHandle>,
+    notifications: Sender<String>,
+}
+
+impl JsonRpc {
+    // Timer notifications show up on the backend loop, long after the
+    // request that set them up, so they go out through notifications.
+    pub fn new(notifications: Sender<String>) -> JsonRpc {
+        JsonRpc {
+            greeted: false,
+            timers: HashMap::new(),
+            notifications: notifications,
+        }
+    }
+
+    // Runs the request on the line, returns the response to it if there is
+    // one to send.
+    pub fn handle_line(&mut self, app: &mut Application<String>, line: &str) -> Option<String> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => {
+                let err = RpcError::new(PARSE_ERROR, e.to_string());
+                return Some(response(Value::Null, Err(err)));
+            }
+        };
+        let method = match request_method(&request) {
+            Ok(method) => method,
+            // Invalid requests get an answer even without an id, and their
+            // id can't be trusted.
+            Err(err) => return Some(response(Value::Null, Err(err))),
+        };
+        let id = request.get("id").cloned();
+        let result = self.handle_request(app, method, Params(request.get("params")));
+        id.map(|id| response(id, result))
+    }
+
+    fn handle_request(&mut self, app: &mut Application<String>, method: &str, params: Params) -> Result<Value, RpcError> {
+        if method == "hello" {
+            return self.hello(app, &params);
+        }
+        if !self.greeted {
+            return Err(RpcError::new(NO_HELLO, "say hello first".to_string()));
+        }
+        match method {
+            "capabilities" => return Ok(capabilities_json(&app.capabilities())),
+            "show_icon" => {
+                let icon = match (params.opt_string("file")?, params.opt_string("resource")?) {
+                    (Some(file), None) => IconResource::File(file),
+                    (None, Some(resource)) => IconResource::Resource(resource),
+                    _ => return Err(invalid_params("wants one of \"file\" or \"resource\"")),
+                };
+                app.show_icon(icon)?;
+            }
+            "hide_icon" => app.hide_icon()?,
+            "set_tooltip" => app.set_tooltip(params.string("tooltip")?)?,
+            "set_label" => app.set_label(params.opt_string("label")?, params.opt_string("guide")?)?,
+            "add_menu_item" => {
+                let id = params.string("id")?;
+                if app.find_menu_item(&id).is_some() {
+                    return Err(invalid_params(&format!("menu item id \"{}\" is taken", id)));
+                }
+                app.add_menu_item_with(&params.string("label")?, id)?;
+            }
+            "add_menu_separator" => {
+                app.add_menu_separator()?;
+            }
+            "set_menu_item_label" => {
+                let idx = menu_item(app, &params)?;
+                app.set_menu_item_label(idx, &params.string("label")?)?;
+            }
+            "set_menu_item_enabled" => {
+                let idx = menu_item(app, &params)?;
+                app.set_menu_item_enabled(idx, params.bool("enabled")?)?;
+            }
+            "notify" => app.notify(&params.string("title")?, &params.string("body")?)?,
+            "add_timeout" | "add_interval" => self.add_timer(app, &params, method == "add_interval")?,
+            "cancel_timer" => {
+                let id = params.string("id")?;
+                match self.timers.remove(&id) {
+                    Some(timer) => timer.cancel().wait()?,
+                    None => return Err(invalid_params(&format!("no timer with id \"{}\"", id))),
+                }
+            }
+            "set_state" => app.set_state(&params.string("name")?)?,
+            "state" => return Ok(state_json(app)),
+            "quit" => app.quit(),
+            _ => return Err(RpcError::new(METHOD_NOT_FOUND, format!("no method \"{}\"", method))),
+        }
+        Ok(Value::Null)
+    }
+
+    fn hello(&mut self, app: &mut Application<String>, params: &Params) -> Result<Value, RpcError> {
+        let version = params.u64("version")?;
+        if version != PROTOCOL_VERSION {
+            let mut err = RpcError::new(BAD_VERSION, format!("protocol version {} is not supported", version));
+            err.data = Some(json!({"supported": [PROTOCOL_VERSION]}));
+            return Err(err);
+        }
+        self.greeted = true;
+        Ok(json!({
+            "version": PROTOCOL_VERSION,
+            "server": concat!("systray-rs ", env!("CARGO_PKG_VERSION")),
+            "capabilities": capabilities_json(&app.capabilities()),
+        }))
+    }
+
+    fn add_timer(&mut self, app: &mut Application<String>, params: &Params, repeat: bool) -> Result<(), RpcError> {
+        let id = params.string("id")?;
+        let every = Duration::from_millis(params.u64("ms")?);
+        if let Some(old) = self.timers.remove(&id) {
+            old.cancel().wait()?;
+        }
+        let tx = self.notifications.clone();
+        let line = notification("timer", json!({"id": id}));
+        let timer = if repeat {
+            app.add_interval(every, move |_| { tx.send(line.clone()).ok(); })?
+        } else {
+            app.add_timeout(every, move |_| { tx.send(line).ok(); })?
+        };
+        self.timers.insert(id, timer);
+        Ok(())
+    }
+}
+
+fn menu_item(app: &Application<String>, params: &Params) -> Result<u32, RpcError> {
+    let id = params.string("id")?;
+    app.find_menu_item(&id)
+        .ok_or_else(|| invalid_params(&format!("no menu item with id \"{}\"", id)))
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> String {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(err) => {
+            let mut error = json!({"code": err.code, "message": err.message});
+            if let Some(data) = err.data {
+                error["data"] = data;
+            }
+            json!({"jsonrpc": "2.0", "id": id, "error": error})
+        }
+    }.to_string()
+}
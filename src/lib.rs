@@ -9,36 +9,153 @@ extern crate winapi;
 extern crate kernel32;
 #[cfg(target_os = "windows")]
 extern crate user32;
-#[cfg(target_os = "windows")]
 extern crate libc;
 #[cfg(target_os = "windows")]
 extern crate encoding;
 extern crate widestring;
+#[cfg(feature = "crossbeam-channel")]
+extern crate crossbeam_channel;
+#[cfg(feature = "futures")]
+extern crate futures;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "serde_json")]
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "serde_yaml")]
+extern crate serde_yaml;
+#[cfg(feature = "toml")]
+extern crate toml;
+#[cfg(feature = "dbus-service")]
+extern crate dbus;
+#[cfg(feature = "dbus-service")]
+extern crate dbus_crossroads;
+#[cfg(all(target_os = "linux", feature = "hot-reload"))]
+extern crate inotify;
 
 #[cfg(target_os = "linux")]
 extern crate gtk;
 #[cfg(target_os = "linux")]
 extern crate glib;
 #[cfg(target_os = "linux")]
-extern crate libappindicator;
+extern crate glib_sys;
+#[cfg(target_os = "linux")]
+extern crate gio_sys;
+#[cfg(target_os = "linux")]
+extern crate gobject_sys;
+#[cfg(target_os = "linux")]
+extern crate gtk_sys;
+#[cfg(target_os = "linux")]
+extern crate libappindicator_sys;
+#[cfg(target_os = "linux")]
+#[macro_use]
+extern crate lazy_static;
 
 pub mod api;
+mod builder;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(unix)]
+pub mod control;
+#[cfg(feature = "dbus-service")]
+pub mod dbus_service;
+mod handle;
+#[cfg(feature = "json-rpc")]
+pub mod json_rpc;
+pub mod protocol;
+#[cfg(all(target_os = "linux", feature = "hot-reload"))]
+mod reload;
+mod sink;
+mod states;
+
+#[cfg(feature = "mock")]
+#[doc(hidden)]
+pub use api::mock::{MockTray, MockMenuEntry};
+pub use builder::{Backend, Builder, Menu};
+pub use handle::{TimerHandle, TimerTray, TrayHandle, Reply};
+pub use sink::EventSink;
+pub use states::{StateBundle, TrayStates};
 
-use std::sync::mpsc::Sender;
+use api::{BackendWindow, TimerTick};
+use sink::Sinks;
+
+#[cfg(feature = "async")]
+use futures::Stream;
+use std::any::Any;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+// With the serde feature the data types below, Menu, MockMenuEntry and
+// protocol::Command serialize the usual serde way, which wire protocols and
+// config files can count on staying put:
+//
+//   - structs are maps keyed by field name. Capabilities fields that are
+//     missing read as false, so older data keeps working when it grows.
+//   - enum variants are snake_case. Ones without data are plain strings,
+//     others are a map with the variant as the only key:
+//
+//         "quit"
+//         {"menu_item_click": "open"}
+//         {"file": "/usr/share/icons/backup.png"}
+//         {"item": [0, "Run now", true]}       MenuEntryState: index, label, enabled
+//
+//   - a Menu is a list of {"item": {"label": "Run now", "payload": ...}},
+//     {"check": {"label": ..., "checked": true, "payload": ...}}, the same
+//     for "radio", {"submenu": {"label": ..., "entries": [...]}} and
+//     "separator" entries. Items with a callback rather than a payload can't
+//     be serialized.
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum SystrayError {
     OsError(String),
     NotImplementedError,
     UnknownError,
     ShowIconWithoutSetError,
     SetToolTipError(String),
+    UnknownMenuItem(u32),
+    BackendGone,
+    Timeout,
+    EventSinkClosed,
+    // Application::set_state with a name no state was registered under.
+    UnknownState(String),
+    // Reply::wait called from a timer callback, which runs on the backend
+    // loop that may have to answer.
+    WaitInTimer,
+    // A timer interval longer than the backends can count in milliseconds.
+    IntervalTooLong,
+    // A menu item that can't do what was asked, like checking a plain item
+    // or adding entries to something other than a submenu.
+    WrongMenuItemKind(u32),
 }
 
-pub enum SystrayEvent{
-    MenuItemClick(u32),
+// T is whatever the application attached to its menu items, plain
+// applications get the item index.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SystrayEvent<T = u32> {
+    MenuItemClick(T),
     LeftButtonClick,
+    // The tray host (explorer, plasmashell, a panel...) restarted. The tray
+    // state has already been replayed to it when this arrives.
+    HostChanged,
+    // A watched file changed and the tray now shows what is in it, see
+    // Application::enable_hot_reload. Carries the path of the file.
+    Reloaded(String),
+    // A watched file changed but could not be used, the tray stays as it
+    // was. Carries what went wrong, file name included.
+    ReloadFailed(String),
+    // Last event there is, the backend has shut down and the channel closes
+    // right after.
+    Quit,
 }
 
 impl std::fmt::Display for SystrayError {
@@ -49,136 +166,875 @@ impl std::fmt::Display for SystrayError {
             &SystrayError::UnknownError => write!(f, "Unknown error occurrred"),
             &SystrayError::ShowIconWithoutSetError => write!(f, "want show icon but icon is none"),
             &SystrayError::SetToolTipError(ref err_str) => write!(f, "SetToolTipError {}",err_str),
+            &SystrayError::UnknownMenuItem(idx) => write!(f, "no menu item with index {}", idx),
+            &SystrayError::BackendGone => write!(f, "backend thread is gone"),
+            &SystrayError::Timeout => write!(f, "backend did not answer in time"),
+            &SystrayError::EventSinkClosed => write!(f, "nobody is listening for events anymore"),
+            &SystrayError::UnknownState(ref name) => write!(f, "no tray state named {}", name),
+            &SystrayError::WaitInTimer => write!(f, "can't wait for the tray from a timer callback"),
+            &SystrayError::IntervalTooLong => write!(f, "timer interval too long"),
+            &SystrayError::WrongMenuItemKind(idx) => write!(f, "menu item {} is the wrong kind of item for that", idx),
         }
     }
 }
 
 #[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum IconResource {
     File(String),
     Resource(String),
 }
 
-#[derive(Clone,PartialEq)]
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum IconStatus {
     SHOW,
     HIDE,
 }
 
-#[derive(Clone)]
+// What the running backend can actually do. Everything defaults to
+// unsupported, backends switch on what they implement.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Capabilities {
+    pub left_click: bool,
+    pub tooltip: bool,
+    pub icon_from_buffer: bool,
+    pub submenus: bool,
+    pub check_items: bool,
+    pub notifications: bool,
+    pub attention: bool,
+    pub hide_show: bool,
+    pub label: bool,
+}
+
+// StatusNotifierItem categories, hosts use them to group icons.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum IndicatorCategory {
+    ApplicationStatus,
+    Communications,
+    SystemServices,
+    Hardware,
+}
+
+// What a backend is told when it is created, before anything is shown.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TrayConfig {
+    pub id: String,
+    pub title: String,
+    pub category: IndicatorCategory,
+    pub icon: Option<IconResource>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TrayIcon {
     pub status: IconStatus,
     pub resource: IconResource
 }
 
-pub struct Application {
-    window: api::api::Window,
-    icon: Option<TrayIcon>,
-    tooltip:String,
-    menu_idx: u32,
-    callback: HashMap<u32, Callback>,
+// What the tray shows right now, see Application::state.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TrayState {
+    // The last icon shown, also while it is hidden.
+    pub icon: Option<IconResource>,
+    pub visible: bool,
+    pub tooltip: String,
+    pub label: String,
+    pub label_guide: String,
+    pub menu: Vec<MenuEntryState>,
+    // Name of the tray state last applied, see Application::set_state.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub current_state: Option<String>,
 }
 
-type Callback = Box<(Fn(&mut Application) -> () + 'static)>;
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum MenuEntryState {
+    // Index, label and whether it is enabled.
+    Item(u32, String, bool),
+    // Same, plus whether it is checked.
+    Check(u32, String, bool, bool),
+    Radio(u32, String, bool, bool),
+    // Same as Item, plus the entries of the submenu.
+    Submenu(u32, String, bool, Vec<MenuEntryState>),
+    Separator(u32),
+}
 
-fn make_callback<F>(f: F) -> Callback
-    where F: std::ops::Fn(&mut Application) -> () + 'static {
-    Box::new(f) as Callback
+// What a menu item is besides its label. Check items flip their mark when
+// clicked, radio items take it from the other radio items of the same menu.
+// Submenus open their entries and send no clicks of their own. Backends
+// have them where Capabilities::check_items and submenus say so.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum MenuItemKind {
+    Plain,
+    // Whether the item is checked.
+    Check(bool),
+    Radio(bool),
+    Submenu,
 }
 
-impl Application {
-    pub fn new(event_tx:Sender<SystrayEvent>) -> Result<Application, SystrayError> {
-        match api::api::Window::new(event_tx) {
-            Ok(w) => Ok(Application {
-                window: w,
-                icon: None,
-                tooltip: "".to_string(),
-                menu_idx: 0,
-                callback: HashMap::new(),
-            }),
-            Err(e) => Err(e)
+impl MenuItemKind {
+    fn same_kind(self, other: MenuItemKind) -> bool {
+        match (self, other) {
+            (MenuItemKind::Plain, MenuItemKind::Plain) => true,
+            (MenuItemKind::Check(_), MenuItemKind::Check(_)) => true,
+            (MenuItemKind::Radio(_), MenuItemKind::Radio(_)) => true,
+            (MenuItemKind::Submenu, MenuItemKind::Submenu) => true,
+            _ => false,
         }
     }
+}
 
-    pub fn add_menu_item<F>(&mut self, item_name: &String, f: F) -> Result<u32, SystrayError>
-        where F: std::ops::Fn(&mut Application) -> () + 'static {
-        let idx = self.menu_idx;
-        if let Err(e) = self.window.add_menu_entry(idx, item_name) {
-            return Err(e);
+// Items carry their label, whether they are enabled and their kind. Every
+// entry knows the submenu item it sits in, None is the tray menu itself.
+// Submenu entries always come after their submenu item.
+#[derive(Clone)]
+enum MenuEntry {
+    Item(u32, Option<u32>, String, bool, MenuItemKind),
+    Separator(u32, Option<u32>),
+}
+
+impl MenuEntry {
+    fn idx(&self) -> u32 {
+        match *self {
+            MenuEntry::Item(idx, ..) | MenuEntry::Separator(idx, _) => idx,
         }
-        self.callback.insert(idx, make_callback(f));
+    }
+
+    fn parent(&self) -> Option<u32> {
+        match *self {
+            MenuEntry::Item(_, parent, ..) | MenuEntry::Separator(_, parent) => parent,
+        }
+    }
+}
+
+// A menu entry as Inner::update_menu should leave it, items with their label,
+// whether they are enabled and their kind. Parents are positions in the same
+// list, of an earlier submenu item.
+#[cfg(feature = "config")]
+enum NewMenuEntry {
+    Item(String, bool, MenuItemKind, Option<usize>),
+    Separator(Option<usize>),
+}
+
+// The backend window plus everything we have told it, so the whole tray can
+// be replayed when the host restarts and forgets about us.
+struct Inner {
+    window: Box<dyn BackendWindow>,
+    icon: Option<TrayIcon>,
+    tooltip: String,
+    label: String,
+    label_guide: String,
+    attention: bool,
+    attention_icon: Option<IconResource>,
+    menu: Vec<MenuEntry>,
+    menu_idx: u32,
+    timer_idx: u32,
+    states: TrayStates,
+    current_state: Option<String>,
+    closed: bool,
+    // Wakes the hot reload watcher to look at a new icon, and stops it by
+    // going away, see reload.rs.
+    #[cfg(all(target_os = "linux", feature = "hot-reload"))]
+    reload_wake: Option<std::os::unix::net::UnixStream>,
+    // Called after every change to what state() shows, until they return
+    // false. See dbus_service.rs.
+    observers: Vec<Box<dyn Fn() -> bool + Send>>,
+}
+
+impl Inner {
+    fn set_icon(&self, icon: &IconResource) -> Result<(), SystrayError> {
+        match *icon {
+            IconResource::File(ref f) => self.window.set_icon_from_file(f)?,
+            IconResource::Resource(ref r) => self.window.set_icon_from_resource(r)?,
+        }
+        self.window.set_tooltip(&self.tooltip)
+    }
+
+    fn add_menu_item(&mut self, item_name: &String) -> Result<u32, SystrayError> {
+        self.add_menu_item_in(None, item_name, MenuItemKind::Plain, true)
+    }
+
+    // Parent has to be a submenu item, None is the tray menu itself.
+    fn add_menu_item_in(&mut self, parent: Option<u32>, item_name: &String, kind: MenuItemKind,
+                        enabled: bool) -> Result<u32, SystrayError> {
+        self.check_parent(parent)?;
+        let idx = self.menu_idx;
+        self.add_menu_entry(idx, parent, item_name, kind, enabled)?;
+        self.menu.push(MenuEntry::Item(idx, parent, item_name.clone(), enabled, kind));
         self.menu_idx += 1;
+        if kind == MenuItemKind::Radio(true) {
+            let pos = self.menu.len() - 1;
+            self.mark_checked(pos, true);
+        }
+        self.changed();
         Ok(idx)
     }
 
-    pub fn add_menu_separator(&mut self) -> Result<u32, SystrayError> {
-        let idx = self.menu_idx;
-        if let Err(e) = self.window.add_menu_separator(idx) {
-            return Err(e);
+    fn check_parent(&self, parent: Option<u32>) -> Result<(), SystrayError> {
+        match parent {
+            Some(parent) => match self.menu_item(parent)?.4 {
+                MenuItemKind::Submenu => Ok(()),
+                _ => Err(SystrayError::WrongMenuItemKind(parent)),
+            },
+            None => Ok(()),
         }
+    }
+
+    // Position, parent, label, whether it is enabled and kind of an item.
+    fn menu_item(&self, item_idx: u32) -> Result<(usize, Option<u32>, String, bool, MenuItemKind), SystrayError> {
+        for (pos, entry) in self.menu.iter().enumerate() {
+            if let MenuEntry::Item(idx, parent, ref label, enabled, kind) = *entry {
+                if idx == item_idx {
+                    return Ok((pos, parent, label.clone(), enabled, kind));
+                }
+            }
+        }
+        Err(SystrayError::UnknownMenuItem(item_idx))
+    }
+
+    fn menu_item_pos(&self, item_idx: u32) -> Result<usize, SystrayError> {
+        self.menu_item(item_idx).map(|item| item.0)
+    }
+
+    // Backends may re-enable an item when relabelling it, so this puts a
+    // disabled one back the way it was.
+    fn add_menu_entry(&self, item_idx: u32, parent: Option<u32>, item_name: &str, kind: MenuItemKind,
+                      enabled: bool) -> Result<(), SystrayError> {
+        self.window.add_menu_entry(item_idx, parent, item_name, kind)?;
+        if !enabled {
+            self.window.set_menu_item_enabled(item_idx, false)?;
+        }
+        Ok(())
+    }
+
+    fn set_menu_item_label(&mut self, item_idx: u32, item_name: &String) -> Result<(), SystrayError> {
+        let (pos, parent, _, enabled, kind) = self.menu_item(item_idx)?;
+        self.add_menu_entry(item_idx, parent, item_name, kind, enabled)?;
+        self.menu[pos] = MenuEntry::Item(item_idx, parent, item_name.clone(), enabled, kind);
+        self.changed();
+        Ok(())
+    }
+
+    fn set_menu_item_enabled(&mut self, item_idx: u32, enabled: bool) -> Result<(), SystrayError> {
+        let pos = self.menu_item_pos(item_idx)?;
+        self.window.set_menu_item_enabled(item_idx, enabled)?;
+        if let MenuEntry::Item(_, _, _, ref mut e, _) = self.menu[pos] {
+            *e = enabled;
+        }
+        self.changed();
+        Ok(())
+    }
+
+    // Only for check and radio items.
+    fn set_menu_item_checked(&mut self, item_idx: u32, checked: bool) -> Result<(), SystrayError> {
+        let (pos, parent, label, enabled, kind) = self.menu_item(item_idx)?;
+        let kind = match kind {
+            MenuItemKind::Check(_) => MenuItemKind::Check(checked),
+            MenuItemKind::Radio(_) => MenuItemKind::Radio(checked),
+            _ => return Err(SystrayError::WrongMenuItemKind(item_idx)),
+        };
+        self.add_menu_entry(item_idx, parent, &label, kind, enabled)?;
+        self.mark_checked(pos, checked);
+        self.changed();
+        Ok(())
+    }
+
+    // Remembers the mark of a check or radio item. A radio item taking it
+    // clears the other radio items of its menu, as backends do on their side.
+    fn mark_checked(&mut self, pos: usize, checked: bool) {
+        let (item_idx, parent) = (self.menu[pos].idx(), self.menu[pos].parent());
+        let radio = match self.menu[pos] {
+            MenuEntry::Item(_, _, _, _, ref mut kind) => match *kind {
+                MenuItemKind::Check(_) => {
+                    *kind = MenuItemKind::Check(checked);
+                    false
+                }
+                MenuItemKind::Radio(_) => {
+                    *kind = MenuItemKind::Radio(checked);
+                    true
+                }
+                _ => false,
+            },
+            MenuEntry::Separator(..) => false,
+        };
+        if !radio || !checked {
+            return;
+        }
+        for entry in self.menu.iter_mut() {
+            if let MenuEntry::Item(idx, p, _, _, ref mut kind) = *entry {
+                if idx != item_idx && p == parent && kind.same_kind(MenuItemKind::Radio(false)) {
+                    *kind = MenuItemKind::Radio(false);
+                }
+            }
+        }
+    }
+
+    // The backend has already moved the mark of a clicked check or radio
+    // item, this catches up with it.
+    fn menu_item_clicked(&mut self, item_idx: u32) {
+        let (pos, _, _, _, kind) = match self.menu_item(item_idx) {
+            Ok(item) => item,
+            Err(_) => return,
+        };
+        match kind {
+            MenuItemKind::Check(checked) => self.mark_checked(pos, !checked),
+            MenuItemKind::Radio(_) => self.mark_checked(pos, true),
+            _ => return,
+        }
+        self.changed();
+    }
+
+    fn add_menu_separator(&mut self) -> Result<u32, SystrayError> {
+        self.add_menu_separator_in(None)
+    }
+
+    fn add_menu_separator_in(&mut self, parent: Option<u32>) -> Result<u32, SystrayError> {
+        self.check_parent(parent)?;
+        let idx = self.menu_idx;
+        self.window.add_menu_separator(idx, parent)?;
+        self.menu.push(MenuEntry::Separator(idx, parent));
         self.menu_idx += 1;
+        self.changed();
         Ok(idx)
     }
 
-    pub fn hide_icon(&mut self) -> Result<(), SystrayError> {
+    // Turns the menu into entries while touching the backend as little as it
+    // can. The leading entries that keep their kind and parent keep their
+    // index too and are only relabelled, re-marked or re-enabled where they
+    // changed, everything after them is removed and added again. Returns the
+    // indexes of the entries.
+    #[cfg(feature = "config")]
+    fn update_menu(&mut self, entries: Vec<NewMenuEntry>) -> Result<Vec<u32>, SystrayError> {
+        let keep = {
+            let menu = &self.menu;
+            let parent_pos = |entry: &MenuEntry| {
+                entry.parent().and_then(|parent| menu.iter().position(|e| e.idx() == parent))
+            };
+            menu.iter().zip(entries.iter()).take_while(|&(old, new)| match (old, new) {
+                (&MenuEntry::Item(_, _, _, _, kind), &NewMenuEntry::Item(_, _, new_kind, parent)) => {
+                    kind.same_kind(new_kind) && parent_pos(old) == parent
+                }
+                (&MenuEntry::Separator(..), &NewMenuEntry::Separator(parent)) => parent_pos(old) == parent,
+                _ => false,
+            }).count()
+        };
+        // From the end, so submenu entries go before their submenu.
+        while self.menu.len() > keep {
+            let idx = self.menu[self.menu.len() - 1].idx();
+            self.window.remove_menu_entry(idx)?;
+            self.menu.pop();
+        }
+        let mut idxs: Vec<u32> = Vec::new();
+        for (pos, entry) in entries.into_iter().enumerate() {
+            if pos >= keep {
+                let idx = match entry {
+                    NewMenuEntry::Item(label, enabled, kind, parent) => {
+                        let parent = parent.map(|p| idxs[p]);
+                        self.add_menu_item_in(parent, &label, kind, enabled)?
+                    }
+                    NewMenuEntry::Separator(parent) => {
+                        let parent = parent.map(|p| idxs[p]);
+                        self.add_menu_separator_in(parent)?
+                    }
+                };
+                idxs.push(idx);
+                continue;
+            }
+            let (idx, parent) = (self.menu[pos].idx(), self.menu[pos].parent());
+            idxs.push(idx);
+            let (label, enabled, kind) = match entry {
+                NewMenuEntry::Item(label, enabled, kind, _) => (label, enabled, kind),
+                NewMenuEntry::Separator(_) => continue,
+            };
+            let (old_label, old_enabled, old_kind) = match self.menu[pos] {
+                MenuEntry::Item(_, _, ref label, enabled, kind) => (label.clone(), enabled, kind),
+                MenuEntry::Separator(..) => continue,
+            };
+            if label != old_label || kind != old_kind {
+                self.add_menu_entry(idx, parent, &label, kind, enabled)?;
+            } else if enabled != old_enabled {
+                self.window.set_menu_item_enabled(idx, enabled)?;
+            }
+            self.menu[pos] = MenuEntry::Item(idx, parent, label, enabled, kind);
+        }
+        self.changed();
+        Ok(idxs)
+    }
+
+    fn hide_icon(&mut self) -> Result<(), SystrayError> {
         let _ = self.window.delete_icon();
         if let Some(ref mut icon) = self.icon {
             icon.status = IconStatus::HIDE;
         };
+        self.changed();
         Ok(())
     }
 
-    pub fn show_icon(&mut self,icon:IconResource) -> Result<(), SystrayError> {
+    fn show_icon(&mut self, icon: IconResource) -> Result<(), SystrayError> {
+        let shown = match self.icon {
+            Some(ref exist_icon) => exist_icon.status == IconStatus::SHOW && exist_icon.resource == icon,
+            None => false,
+        };
+        if !shown {
+            self.set_icon(&icon)?;
+            self.icon = Some(TrayIcon {
+                resource: icon,
+                status: IconStatus::SHOW
+            });
+            #[cfg(all(target_os = "linux", feature = "hot-reload"))]
+            reload::wake(self);
+            self.changed();
+        }
+        Ok(())
+    }
+
+    fn set_tooltip(&mut self, tooltip: String) -> Result<(), SystrayError> {
+        self.tooltip = tooltip;
+        self.changed();
+        // A hidden icon picks the tooltip up in show_icon.
         match self.icon {
-            Some(ref mut exist_icon) => {
-                if exist_icon.status == IconStatus::HIDE || exist_icon.resource != icon {
-                    match icon.clone() {
-                        IconResource::File(f) => {
-                            self.window.set_icon_from_file(&f.clone())?;
-                            self.window.set_tooltip(&self.tooltip)?;
-                        }
-
-                        IconResource::Resource(r) => {
-                            self.window.set_icon_from_resource(&r.clone())?;
-                            self.window.set_tooltip(&self.tooltip)?;
-                        }
-                    }
+            Some(ref icon) if icon.status == IconStatus::SHOW => self.window.set_tooltip(&self.tooltip),
+            _ => Ok(())
+        }
+    }
+
+    // An empty label removes it. Repeating the current label is free, so
+    // callers can update it as often as they like.
+    fn set_label(&mut self, label: Option<String>, guide: Option<String>) -> Result<(), SystrayError> {
+        let label = label.unwrap_or_default();
+        let guide = guide.unwrap_or_default();
+        if label == self.label && guide == self.label_guide {
+            return Ok(());
+        }
+        self.window.set_label(&label, &guide)?;
+        self.label = label;
+        self.label_guide = guide;
+        self.changed();
+        Ok(())
+    }
 
-                    exist_icon.status = IconStatus::SHOW;
+    // Tells the observers state() may look different now.
+    fn changed(&mut self) {
+        self.observers.retain(|observer| observer());
+    }
+
+    // Notifications are fire and forget, nothing to replay for them.
+    fn notify(&self, title: &str, body: &str) -> Result<(), SystrayError> {
+        self.window.notify(title, body)
+    }
+
+    // Timers live on the backend loop alone, a new host has no use for them
+    // so they are not part of the replayed state.
+    fn add_timer(&mut self, interval: Duration, tick: TimerTick) -> Result<u32, SystrayError> {
+        let idx = self.timer_idx;
+        self.window.add_timer(idx, interval, tick)?;
+        self.timer_idx += 1;
+        Ok(idx)
+    }
+
+    // Removes the icon, stops the backend loop and waits for its thread.
+    // Later calls do nothing.
+    fn quit(&mut self) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+        #[cfg(all(target_os = "linux", feature = "hot-reload"))]
+        self.reload_wake.take();
+        let _ = self.hide_icon();
+        self.window.quit()
+    }
+
+    fn state(&self) -> TrayState {
+        TrayState {
+            icon: self.icon.as_ref().map(|icon| icon.resource.clone()),
+            visible: self.icon.as_ref().map_or(false, |icon| icon.status == IconStatus::SHOW),
+            tooltip: self.tooltip.clone(),
+            label: self.label.clone(),
+            label_guide: self.label_guide.clone(),
+            menu: self.menu_state(None),
+            current_state: self.current_state.clone(),
+        }
+    }
+
+    // The entries of a submenu, or of the tray menu for None.
+    fn menu_state(&self, parent: Option<u32>) -> Vec<MenuEntryState> {
+        self.menu.iter().filter(|entry| entry.parent() == parent).map(|entry| match *entry {
+            MenuEntry::Item(idx, _, ref name, enabled, kind) => match kind {
+                MenuItemKind::Plain => MenuEntryState::Item(idx, name.clone(), enabled),
+                MenuItemKind::Check(checked) => MenuEntryState::Check(idx, name.clone(), enabled, checked),
+                MenuItemKind::Radio(checked) => MenuEntryState::Radio(idx, name.clone(), enabled, checked),
+                MenuItemKind::Submenu => {
+                    MenuEntryState::Submenu(idx, name.clone(), enabled, self.menu_state(Some(idx)))
                 }
             },
-            None => {
-                match icon.clone() {
-                    IconResource::File(f) => {
-                        self.window.set_icon_from_file(&f.clone())?;
-                        self.window.set_tooltip(&self.tooltip)?;
+            MenuEntry::Separator(idx, _) => MenuEntryState::Separator(idx),
+        }).collect()
+    }
+
+    // Push the complete remembered state to the backend again. Backends treat
+    // menu indexes they already know as updates, so this is safe to repeat.
+    fn replay(&mut self) -> Result<(), SystrayError> {
+        for entry in self.menu.iter() {
+            match *entry {
+                MenuEntry::Item(idx, parent, ref name, enabled, kind) => {
+                    self.add_menu_entry(idx, parent, name, kind, enabled)?
+                }
+                MenuEntry::Separator(idx, parent) => self.window.add_menu_separator(idx, parent)?,
+            }
+        }
+        if let Some(ref icon) = self.icon {
+            if icon.status == IconStatus::SHOW {
+                self.set_icon(&icon.resource)?;
+            }
+        }
+        if !self.label.is_empty() {
+            self.window.set_label(&self.label, &self.label_guide)?;
+        }
+        if let Some(ref icon) = self.attention_icon {
+            self.window.set_attention_icon(icon)?;
+        }
+        if self.attention {
+            self.window.set_attention(true)?;
+        }
+        Ok(())
+    }
+}
+
+fn lock_inner<'a>(inner: &'a Arc<Mutex<Inner>>) -> MutexGuard<'a, Inner> {
+    // A panic while holding the lock leaves the state no worse than a failed
+    // backend call would, so keep going with it.
+    inner.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+type Payloads<T> = Arc<Mutex<HashMap<u32, T>>>;
+// Shared with the application so the sink can be swapped out, see events(),
+// and copies added, see serve_dbus.
+type SharedSink<T> = Arc<Mutex<Sinks<T>>>;
+
+// Payload for a click on an item that was added without one. That only works
+// out for plain u32 applications, which get the index itself. Other
+// applications only get to run the item's callback.
+fn index_payload<T: Any>(item_idx: u32) -> Option<T> {
+    (Box::new(item_idx) as Box<dyn Any>).downcast::<T>().ok().map(|p| *p)
+}
+
+// Sits between the backend and the user's sink so the core gets to react to
+// backend events, and swap menu indexes for payloads, before passing them on.
+// Clicks on items without a payload go to clicks as well, for the callbacks
+// the application runs on its own thread.
+fn spawn_event_pump<T>(inner: Arc<Mutex<Inner>>,
+                       payloads: Payloads<T>,
+                       backend_rx: Receiver<SystrayEvent>,
+                       clicks: Sender<u32>,
+                       sink: SharedSink<T>) -> thread::JoinHandle<()>
+    where T: Clone + Send + 'static {
+    thread::spawn(move || {
+        for event in backend_rx.iter() {
+            let event = match event {
+                SystrayEvent::MenuItemClick(idx) => {
+                    lock_inner(&inner).menu_item_clicked(idx);
+                    let payload = payloads.lock().unwrap_or_else(|e| e.into_inner())
+                        .get(&idx).cloned();
+                    if payload.is_none() {
+                        clicks.send(idx).ok();
+                    }
+                    match payload.or_else(|| index_payload(idx)) {
+                        Some(payload) => SystrayEvent::MenuItemClick(payload),
+                        None => continue,
                     }
-                    IconResource::Resource(r) => {
-                        self.window.set_icon_from_resource(&r.clone())?;
-                        self.window.set_tooltip(&self.tooltip)?;
+                }
+                SystrayEvent::LeftButtonClick => SystrayEvent::LeftButtonClick,
+                SystrayEvent::HostChanged => {
+                    if let Err(e) = lock_inner(&inner).replay() {
+                        warn!("Replaying tray state to the new host failed: {}", e);
                     }
+                    SystrayEvent::HostChanged
                 }
-                self.icon = Some(TrayIcon {
-                    resource:icon.clone(),
-                    status:IconStatus::SHOW
-                })
+                // Only ever sent by the reload watcher, straight to the sink.
+                SystrayEvent::Reloaded(file) => SystrayEvent::Reloaded(file),
+                SystrayEvent::ReloadFailed(msg) => SystrayEvent::ReloadFailed(msg),
+                SystrayEvent::Quit => SystrayEvent::Quit,
+            };
+            let sent = sink.lock().unwrap_or_else(|e| e.into_inner()).send_event(event);
+            if let Err(e) = sent {
+                info!("Shutting the tray down: {}", e);
+                lock_inner(&inner).quit();
+                return;
             }
-        };
+        }
+        // The backend only hangs up once its loop is done. Dropping the sink
+        // right after is what closes the user's channel.
+        let mut sinks = sink.lock().unwrap_or_else(|e| e.into_inner());
+        sinks.send_event(SystrayEvent::Quit).ok();
+        *sinks = Sinks::new(Box::new(|_: SystrayEvent<T>| {}));
+    })
+}
+
+pub struct Application<T = u32> {
+    inner: Arc<Mutex<Inner>>,
+    payloads: Payloads<T>,
+    sink: SharedSink<T>,
+    jobs: Sender<handle::Job>,
+    event_pump: Option<thread::JoinHandle<()>>,
+    callback: HashMap<u32, Callback<T>>,
+    // Indexes of clicked items that may have a callback, see
+    // wait_for_message. Closes when the event pump is done.
+    clicks: Receiver<u32>,
+    // The tray spec the application was built from and how to apply it
+    // again, see Application::from_config.
+    #[cfg(all(target_os = "linux", feature = "hot-reload"))]
+    spec_file: Option<(std::path::PathBuf, reload::SpecReload<T>)>,
+}
+
+type Callback<T> = Box<dyn Fn(&mut Application<T>) -> () + 'static>;
+
+fn make_callback<T, F>(f: F) -> Callback<T>
+    where F: std::ops::Fn(&mut Application<T>) -> () + 'static {
+    Box::new(f) as Callback<T>
+}
+
+impl Application {
+    pub fn new(event_tx:Sender<SystrayEvent>) -> Result<Application, SystrayError> {
+        Application::builder().event_sink(event_tx).build()
+    }
+
+    // For applications with their own payload type use Builder::<T>::new().
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+}
+
+impl<T: Clone + Send + 'static> Application<T> {
+    fn from_window(window: Box<dyn BackendWindow>,
+                   backend_rx: Receiver<SystrayEvent>,
+                   sink: Box<dyn EventSink<T>>) -> Application<T> {
+        let inner = Arc::new(Mutex::new(Inner {
+            window: window,
+            icon: None,
+            tooltip: "".to_string(),
+            label: "".to_string(),
+            label_guide: "".to_string(),
+            attention: false,
+            attention_icon: None,
+            menu: Vec::new(),
+            menu_idx: 0,
+            timer_idx: 0,
+            states: TrayStates::new(),
+            current_state: None,
+            closed: false,
+            #[cfg(all(target_os = "linux", feature = "hot-reload"))]
+            reload_wake: None,
+            observers: Vec::new(),
+        }));
+        let payloads = Arc::new(Mutex::new(HashMap::new()));
+        let sink = Arc::new(Mutex::new(Sinks::new(sink)));
+        let (clicks_tx, clicks) = channel();
+        let event_pump = spawn_event_pump(inner.clone(), payloads.clone(), backend_rx, clicks_tx, sink.clone());
+        Application {
+            jobs: handle::spawn_worker(inner.clone()),
+            event_pump: Some(event_pump),
+            inner: inner,
+            payloads: payloads,
+            sink: sink,
+            callback: HashMap::new(),
+            clicks: clicks,
+            #[cfg(all(target_os = "linux", feature = "hot-reload"))]
+            spec_file: None,
+        }
+    }
+
+    // Clicks on the new item arrive as MenuItemClick(payload).
+    pub fn add_menu_item_with(&mut self, item_name: &String, payload: T) -> Result<u32, SystrayError> {
+        // With the payload in before anyone else looks at the new item.
+        let mut inner = self.inner();
+        let idx = inner.add_menu_item(item_name)?;
+        self.payloads.lock().unwrap_or_else(|e| e.into_inner()).insert(idx, payload);
+        Ok(idx)
+    }
+
+    // A check or radio item, or any item in a submenu. Parent is the index of
+    // a submenu item, None the tray menu itself. Clicks arrive as
+    // MenuItemClick(payload), once the item has its new mark, see state().
+    pub fn add_menu_entry_with(&mut self, parent: Option<u32>, item_name: &String, kind: MenuItemKind,
+                               payload: T) -> Result<u32, SystrayError> {
+        let mut inner = self.inner();
+        let idx = inner.add_menu_item_in(parent, item_name, kind, true)?;
+        self.payloads.lock().unwrap_or_else(|e| e.into_inner()).insert(idx, payload);
+        Ok(idx)
+    }
+
+    // Index of the first item added with this payload.
+    pub fn find_menu_item(&self, payload: &T) -> Option<u32> where T: PartialEq {
+        let payloads = self.payloads.lock().unwrap_or_else(|e| e.into_inner());
+        payloads.iter()
+            .filter(|&(_, p)| p == payload)
+            .map(|(idx, _)| *idx)
+            .min()
+    }
+
+    // Events from here on go to the returned stream instead of the sink the
+    // application was built with, which gets dropped. Copies, like those of
+    // a D-Bus service, keep coming, see serve_dbus. Dropping the stream shuts
+    // the tray down like any other closed sink.
+    #[cfg(feature = "async")]
+    pub fn events(&mut self) -> impl Stream<Item = SystrayEvent<T>, Error = ()> {
+        let (tx, rx) = futures::sync::mpsc::unbounded();
+        self.sink.lock().unwrap_or_else(|e| e.into_inner()).sink = Box::new(tx);
+        rx
+    }
+}
+
+impl<T> Application<T> {
+    fn inner<'a>(&'a self) -> MutexGuard<'a, Inner> {
+        lock_inner(&self.inner)
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.inner().window.capabilities()
+    }
+
+    // What the backend last told the host the icon is, see
+    // BackendWindow::icon_name.
+    #[doc(hidden)]
+    pub fn backend_icon_name(&self) -> Result<Option<String>, SystrayError> {
+        self.inner().window.icon_name()
+    }
+
+    // How long to wait for backends that run commands on their own thread.
+    // None, the default, waits for as long as it takes. A command that times
+    // out is dropped, the backend never runs it.
+    pub fn set_backend_timeout(&mut self, timeout: Option<Duration>) {
+        self.inner().window.set_timeout(timeout)
+    }
+
+    // Clicks on the new item run f, see wait_for_message.
+    pub fn add_menu_item<F>(&mut self, item_name: &String, f: F) -> Result<u32, SystrayError>
+        where F: std::ops::Fn(&mut Application<T>) -> () + 'static {
+        let idx = self.inner().add_menu_item(item_name)?;
+        self.callback.insert(idx, make_callback(f));
+        Ok(idx)
+    }
+
+    // Same as add_menu_entry_with, but clicks run f.
+    pub fn add_menu_entry<F>(&mut self, parent: Option<u32>, item_name: &String, kind: MenuItemKind,
+                             f: F) -> Result<u32, SystrayError>
+        where F: std::ops::Fn(&mut Application<T>) -> () + 'static {
+        let idx = self.inner().add_menu_item_in(parent, item_name, kind, true)?;
+        self.callback.insert(idx, make_callback(f));
+        Ok(idx)
+    }
+
+    // An item that opens a menu of its own, for the entries added with it as
+    // their parent. Parent works as in add_menu_entry_with.
+    pub fn add_submenu(&mut self, parent: Option<u32>, item_name: &String) -> Result<u32, SystrayError> {
+        self.inner().add_menu_item_in(parent, item_name, MenuItemKind::Submenu, true)
+    }
+
+    // Runs the callbacks of clicked menu items on the calling thread, as the
+    // clicks come in, until the tray quits. A callback quitting the
+    // application is what usually ends it.
+    pub fn wait_for_message(&mut self) -> Result<(), SystrayError> {
+        while let Ok(idx) = self.clicks.recv() {
+            self.run_callback(idx);
+        }
         Ok(())
     }
 
+    // Runs the callbacks of the items clicked so far and returns, for
+    // applications with a loop of their own.
+    pub fn run_callbacks(&mut self) {
+        while let Ok(idx) = self.clicks.try_recv() {
+            self.run_callback(idx);
+        }
+    }
+
+    fn run_callback(&mut self, idx: u32) {
+        // Out of the map while it runs, it gets the whole application. Back
+        // in afterwards unless the callback took its item away.
+        if let Some(f) = self.callback.remove(&idx) {
+            f(self);
+            if self.inner().menu_item_pos(idx).is_ok() {
+                self.callback.entry(idx).or_insert(f);
+            }
+        }
+    }
+
+    pub fn set_menu_item_label(&mut self, item_idx: u32, item_name: &String) -> Result<(), SystrayError> {
+        self.inner().set_menu_item_label(item_idx, item_name)
+    }
+
+    pub fn add_menu_separator(&mut self) -> Result<u32, SystrayError> {
+        self.inner().add_menu_separator()
+    }
+
+    pub fn add_menu_separator_in(&mut self, parent: Option<u32>) -> Result<u32, SystrayError> {
+        self.inner().add_menu_separator_in(parent)
+    }
+
+    pub fn state(&self) -> TrayState {
+        self.inner().state()
+    }
+
+    // Only check and radio items have a mark. Checking a radio item clears
+    // the others in its menu.
+    pub fn set_menu_item_checked(&mut self, item_idx: u32, checked: bool) -> Result<(), SystrayError> {
+        self.inner().set_menu_item_checked(item_idx, checked)
+    }
+
+    // Disabled items are greyed out and can't be clicked.
+    pub fn set_menu_item_enabled(&mut self, item_idx: u32, enabled: bool) -> Result<(), SystrayError> {
+        self.inner().set_menu_item_enabled(item_idx, enabled)
+    }
+
+    // A message popping up from the tray icon, where the backend has them,
+    // see Capabilities::notifications.
+    pub fn notify(&mut self, title: &str, body: &str) -> Result<(), SystrayError> {
+        self.inner().notify(title, body)
+    }
+
+    pub fn hide_icon(&mut self) -> Result<(), SystrayError> {
+        self.inner().hide_icon()
+    }
+
+    pub fn show_icon(&mut self,icon:IconResource) -> Result<(), SystrayError> {
+        self.inner().show_icon(icon)
+    }
+
     pub fn set_tooltip(&mut self, tooltip: String) -> Result<(), SystrayError> {
-        self.tooltip = tooltip;
-        Ok(())
+        self.inner().set_tooltip(tooltip)
+    }
+
+    // Text shown next to the icon where the host supports it, see
+    // Capabilities::label. The guide is the longest text the label is
+    // expected to hold, so the host can reserve room and not jump around.
+    pub fn set_label(&mut self, label: Option<String>, guide: Option<String>) -> Result<(), SystrayError> {
+        self.inner().set_label(label, guide)
     }
 
     pub fn quit(&mut self) {
-        let _ = self.hide_icon();
-        self.window.quit()
+        self.inner().quit();
+        // Not while holding the lock, the pump may still need it to finish a
+        // replay.
+        if let Some(pump) = self.event_pump.take() {
+            pump.join().ok();
+        }
     }
 }
 
-impl Drop for Application {
+impl<T> Drop for Application<T> {
     fn drop(&mut self) {
         self.quit();
     }
@@ -0,0 +1,373 @@
+// Line based commands for driving a tray from another process, which is what
+// the systray tool reads on stdin. One command per line:
+//
+//     icon <file or icon name>
+//     hide
+//     tooltip <text>
+//     label <text>
+//     add-item <id> <label>
+//     separator
+//     set-enabled <id> true|false
+//     set-label <id> <label>                the item's label, label is the tray's
+//     notify <title> <body>
+//     state
+//     set-state <name>
+//     quit
+//
+// The last argument is the rest of the line. Those before it are single
+// words, or double quoted with \" and \\ escapes when they hold spaces. Menu
+// items are known by the ids the commands gave them, which are also what
+// clicks report. Events go the other way as lines too, see event_line.
+//
+// set-state switches to the tray state of that name, which the application
+// has to have set up, see systray::states. The systray tool sets up none.
+// state is the only command with anything to say back, a line for each of
+// these:
+//
+//     state icon file|resource <name>
+//     state visible true|false
+//     state tooltip <text>
+//     state label <text>
+//     state current <name>                  once a tray state was applied
+//     state item <id> true|false <label>    one per item, true when enabled
+//     state check <id> true|false true|false <label>
+//     state radio <id> true|false true|false <label>
+//                                           enabled, then checked
+//     state submenu <id> true|false <label> the lines of its entries follow,
+//     state end                             up to this one
+//     state separator
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use {Application, IconResource, MenuEntryState, Payloads, SystrayError, SystrayEvent, TrayHandle};
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Command {
+    // A file if one exists at that path, an icon name otherwise.
+    Icon(String),
+    Hide,
+    Tooltip(String),
+    Label(String),
+    AddItem(String, String),
+    Separator,
+    SetEnabled(String, bool),
+    SetItemLabel(String, String),
+    Notify(String, String),
+    State,
+    SetState(String),
+    Quit,
+}
+
+#[derive(Clone, Debug)]
+pub enum CommandError {
+    // The line could not be parsed.
+    BadCommand(String),
+    UnknownId(String),
+    DuplicateId(String),
+    Tray(SystrayError),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CommandError::BadCommand(ref msg) => write!(f, "{}", msg),
+            CommandError::UnknownId(ref id) => write!(f, "no menu item with id {}", quote(id)),
+            CommandError::DuplicateId(ref id) => write!(f, "menu item id {} is taken", quote(id)),
+            CommandError::Tray(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<SystrayError> for CommandError {
+    fn from(e: SystrayError) -> CommandError {
+        CommandError::Tray(e)
+    }
+}
+
+// Splits the next word off the front of s, unquoting it if needed.
+fn next_word(s: &str) -> Result<(String, &str), CommandError> {
+    let s = s.trim_start();
+    if !s.starts_with('"') {
+        let end = s.find(char::is_whitespace).unwrap_or(s.len());
+        return Ok((s[..end].to_string(), &s[end..]));
+    }
+    let mut word = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((word, &s[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, c)) => word.push(c),
+                None => break,
+            },
+            c => word.push(c),
+        }
+    }
+    Err(CommandError::BadCommand("unterminated quote".to_string()))
+}
+
+// The rest of the line, which may be quoted as a whole.
+fn rest(s: &str) -> Result<String, CommandError> {
+    let s = s.trim();
+    if s.starts_with('"') {
+        let (word, after) = next_word(s)?;
+        if after.trim().is_empty() {
+            return Ok(word);
+        }
+    }
+    Ok(s.to_string())
+}
+
+// Words need quoting when they are empty or hold spaces or quotes.
+fn quote(word: &str) -> String {
+    if !word.is_empty() && !word.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return word.to_string();
+    }
+    let mut quoted = "\"".to_string();
+    for c in word.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, CommandError> {
+        let (name, args) = next_word(line)?;
+        let no_args = |command: Command| if args.trim().is_empty() {
+            Ok(command)
+        } else {
+            Err(CommandError::BadCommand(format!("{} takes no arguments", name)))
+        };
+        let command = match &name[..] {
+            "icon" => Command::Icon(rest(args)?),
+            "hide" => no_args(Command::Hide)?,
+            "tooltip" => Command::Tooltip(rest(args)?),
+            "label" => Command::Label(rest(args)?),
+            "add-item" => {
+                let (id, args) = next_word(args)?;
+                Command::AddItem(id, rest(args)?)
+            }
+            "separator" => no_args(Command::Separator)?,
+            "set-enabled" => {
+                let (id, args) = next_word(args)?;
+                let enabled = match &rest(args)?[..] {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(CommandError::BadCommand(
+                        "set-enabled wants true or false".to_string())),
+                };
+                Command::SetEnabled(id, enabled)
+            }
+            "set-label" => {
+                let (id, args) = next_word(args)?;
+                Command::SetItemLabel(id, rest(args)?)
+            }
+            "notify" => {
+                let (title, args) = next_word(args)?;
+                Command::Notify(title, rest(args)?)
+            }
+            "state" => no_args(Command::State)?,
+            "set-state" => Command::SetState(rest(args)?),
+            "quit" => no_args(Command::Quit)?,
+            "" => return Err(CommandError::BadCommand("empty command".to_string())),
+            _ => return Err(CommandError::BadCommand(format!("unknown command {}", quote(&name)))),
+        };
+        match command {
+            Command::Icon(ref arg) | Command::AddItem(_, ref arg) |
+            Command::SetState(ref arg) if arg.is_empty() => {
+                Err(CommandError::BadCommand(format!("{} is missing an argument", name)))
+            }
+            Command::AddItem(ref id, _) | Command::SetEnabled(ref id, _) |
+            Command::SetItemLabel(ref id, _) if id.is_empty() => {
+                Err(CommandError::BadCommand(format!("{} is missing the item id", name)))
+            }
+            command => Ok(command),
+        }
+    }
+
+    // Applies the command to a tray whose menu item payloads are the ids,
+    // returns the lines it has to say back.
+    pub fn run(self, app: &mut Application<String>) -> Result<Vec<String>, CommandError> {
+        match self {
+            // Waits for the backend to shut down, the runner can't.
+            Command::Quit => {
+                app.quit();
+                Ok(Vec::new())
+            }
+            command => app.command_runner().run(command),
+        }
+    }
+}
+
+// Writes the command the way parse reads it.
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Command::Icon(ref icon) => write!(f, "icon {}", quote(icon)),
+            Command::Hide => write!(f, "hide"),
+            Command::Tooltip(ref tooltip) => write!(f, "tooltip {}", quote(tooltip)),
+            Command::Label(ref label) => write!(f, "label {}", quote(label)),
+            Command::AddItem(ref id, ref label) => write!(f, "add-item {} {}", quote(id), quote(label)),
+            Command::Separator => write!(f, "separator"),
+            Command::SetEnabled(ref id, enabled) => write!(f, "set-enabled {} {}", quote(id), enabled),
+            Command::SetItemLabel(ref id, ref label) => write!(f, "set-label {} {}", quote(id), quote(label)),
+            Command::Notify(ref title, ref body) => write!(f, "notify {} {}", quote(title), quote(body)),
+            Command::State => write!(f, "state"),
+            Command::SetState(ref name) => write!(f, "set-state {}", quote(name)),
+            Command::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+// Runs commands through a TrayHandle, so from any thread.
+#[derive(Clone)]
+pub struct CommandRunner {
+    tray: TrayHandle,
+    payloads: Payloads<String>,
+}
+
+impl Application<String> {
+    pub fn command_runner(&self) -> CommandRunner {
+        CommandRunner {
+            tray: self.handle(),
+            payloads: self.payloads.clone(),
+        }
+    }
+}
+
+impl CommandRunner {
+    fn find(&self, id: &str) -> Option<u32> {
+        let payloads = self.payloads.lock().unwrap_or_else(|e| e.into_inner());
+        payloads.iter()
+            .filter(|&(_, p)| p == id)
+            .map(|(idx, _)| *idx)
+            .min()
+    }
+
+    fn item(&self, id: String) -> Result<u32, CommandError> {
+        self.find(&id).ok_or(CommandError::UnknownId(id))
+    }
+
+    pub fn run(&self, command: Command) -> Result<Vec<String>, CommandError> {
+        match command {
+            Command::Icon(icon) => {
+                let icon = if Path::new(&icon).is_file() {
+                    IconResource::File(icon)
+                } else {
+                    IconResource::Resource(icon)
+                };
+                self.tray.show_icon(icon).wait()?
+            }
+            Command::Hide => self.tray.hide_icon().wait()?,
+            Command::Tooltip(tooltip) => self.tray.set_tooltip(tooltip).wait()?,
+            Command::Label(label) => self.tray.set_label(Some(label), None).wait()?,
+            Command::AddItem(id, label) => {
+                // Checked and added in one go on the worker, so two clients
+                // adding the same id can't both get it.
+                let payloads = self.payloads.clone();
+                self.tray.run(move |inner| {
+                    let mut payloads = payloads.lock().unwrap_or_else(|e| e.into_inner());
+                    if payloads.values().any(|other| *other == id) {
+                        return Ok(Err(CommandError::DuplicateId(id)));
+                    }
+                    let idx = inner.add_menu_item(&label)?;
+                    payloads.insert(idx, id);
+                    Ok(Ok(()))
+                }).wait()??
+            }
+            Command::Separator => {
+                self.tray.add_menu_separator().wait()?;
+            }
+            Command::SetEnabled(id, enabled) => {
+                let idx = self.item(id)?;
+                self.tray.set_menu_item_enabled(idx, enabled).wait()?
+            }
+            Command::SetItemLabel(id, label) => {
+                let idx = self.item(id)?;
+                self.tray.set_menu_item_label(idx, label).wait()?
+            }
+            Command::Notify(title, body) => self.tray.notify(title, body).wait()?,
+            Command::State => return self.state(),
+            Command::SetState(name) => self.tray.set_state(name).wait()?,
+            Command::Quit => self.tray.quit().wait()?,
+        }
+        Ok(Vec::new())
+    }
+
+    fn state(&self) -> Result<Vec<String>, CommandError> {
+        let state = self.tray.state().wait()?;
+        let mut lines = Vec::new();
+        match state.icon {
+            Some(IconResource::File(ref file)) => lines.push(format!("state icon file {}", quote(file))),
+            Some(IconResource::Resource(ref name)) => lines.push(format!("state icon resource {}", quote(name))),
+            None => {}
+        }
+        lines.push(format!("state visible {}", state.visible));
+        lines.push(format!("state tooltip {}", quote(&state.tooltip)));
+        lines.push(format!("state label {}", quote(&state.label)));
+        if let Some(ref name) = state.current_state {
+            lines.push(format!("state current {}", quote(name)));
+        }
+        let payloads = self.payloads.lock().unwrap_or_else(|e| e.into_inner());
+        menu_lines(&mut lines, &state.menu, &payloads);
+        Ok(lines)
+    }
+}
+
+fn menu_lines(lines: &mut Vec<String>, menu: &[MenuEntryState], payloads: &HashMap<u32, String>) {
+    let id = |idx: &u32| quote(payloads.get(idx).map_or("", |id| &id[..]));
+    for entry in menu {
+        match *entry {
+            MenuEntryState::Item(ref idx, ref label, enabled) => {
+                lines.push(format!("state item {} {} {}", id(idx), enabled, quote(label)));
+            }
+            MenuEntryState::Check(ref idx, ref label, enabled, checked) => {
+                lines.push(format!("state check {} {} {} {}", id(idx), enabled, checked, quote(label)));
+            }
+            MenuEntryState::Radio(ref idx, ref label, enabled, checked) => {
+                lines.push(format!("state radio {} {} {} {}", id(idx), enabled, checked, quote(label)));
+            }
+            MenuEntryState::Submenu(ref idx, ref label, enabled, ref entries) => {
+                lines.push(format!("state submenu {} {} {}", id(idx), enabled, quote(label)));
+                menu_lines(lines, entries, payloads);
+                lines.push("state end".to_string());
+            }
+            MenuEntryState::Separator(_) => lines.push("state separator".to_string()),
+        }
+    }
+}
+
+// How events are written out: "click <id>", "left-click", "host-changed",
+// "reloaded <file>", "reload-failed <message>" and "quit", the last line
+// there is.
+pub fn event_line(event: &SystrayEvent<String>) -> String {
+    match *event {
+        SystrayEvent::MenuItemClick(ref id) => format!("click {}", quote(id)),
+        SystrayEvent::LeftButtonClick => "left-click".to_string(),
+        SystrayEvent::HostChanged => "host-changed".to_string(),
+        SystrayEvent::Reloaded(ref file) => format!("reloaded {}", quote(file)),
+        SystrayEvent::ReloadFailed(ref msg) => format!("reload-failed {}", quote(msg)),
+        SystrayEvent::Quit => "quit".to_string(),
+    }
+}
+
+// Reply to a command that failed, commands that work get none. The message
+// is quoted like any other last argument, so it stays on one line.
+pub fn error_line(err: &CommandError) -> String {
+    format!("error {}", quote(&err.to_string()))
+}
+
+// The message of a line error_line wrote, None for other lines.
+pub fn error_message(line: &str) -> Option<String> {
+    if !line.starts_with("error ") {
+        return None;
+    }
+    rest(&line["error ".len()..]).ok()
+}
@@ -0,0 +1,219 @@
+// Hot reload: Application::enable_hot_reload watches the icon file the tray
+// shows and the tray spec it was built from, see Application::from_config,
+// and puts them back on the tray whenever they change. Files are watched
+// through their directories so editors that save by renaming a new file over
+// the old one are caught too. The watcher blocks until inotify or the tray
+// has something for it, the tray wakes it when the icon changes and stops
+// it by dropping its end of their socket pair on quit.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use inotify::{Inotify, WatchDescriptor, WatchMask};
+use libc;
+use {lock_inner, Application, IconResource, IconStatus, Inner, Payloads, SharedSink, SystrayError,
+     SystrayEvent};
+
+// Loads the spec at the path and applies it to the tray, see config.rs.
+pub type SpecReload<T> = fn(&mut Inner, &Payloads<T>, &Path) -> Result<(), String>;
+
+impl<T: Clone + Send + 'static> Application<T> {
+    // Changes show up as SystrayEvent::Reloaded, or ReloadFailed when the new
+    // file is no good. Watching stops when the tray quits, calling this again
+    // before that does nothing.
+    pub fn enable_hot_reload(&mut self) -> Result<(), SystrayError> {
+        let mut inner = self.inner();
+        if inner.reload_wake.is_some() || inner.closed {
+            return Ok(());
+        }
+        let inotify = Inotify::init().map_err(|e| SystrayError::OsError(format!("inotify: {}", e)))?;
+        let (wake, woken) = UnixStream::pair()
+            .and_then(|(wake, woken)| wake.set_nonblocking(true).map(|_| (wake, woken)))
+            .map_err(|e| SystrayError::OsError(format!("hot reload: {}", e)))?;
+        let mut watcher = Watcher {
+            inotify: inotify,
+            woken: woken,
+            dirs: HashMap::new(),
+            inner: self.inner.clone(),
+            payloads: self.payloads.clone(),
+            sink: self.sink.clone(),
+            spec: self.spec_file.clone(),
+        };
+        // Changes from here on count, not only those after the thread starts.
+        watcher.watch(shown_icon(&inner).as_ref().map(Path::new));
+        inner.reload_wake = Some(wake);
+        thread::spawn(move || watcher.run());
+        Ok(())
+    }
+}
+
+// Has the watcher look at the icon the tray shows now.
+pub fn wake(inner: &Inner) {
+    if let Some(ref wake) = inner.reload_wake {
+        // A full socket has a wake-up waiting already.
+        (&*wake).write_all(&[0]).ok();
+    }
+}
+
+struct Watcher<T> {
+    inotify: Inotify,
+    woken: UnixStream,
+    dirs: HashMap<PathBuf, WatchDescriptor>,
+    inner: Arc<Mutex<Inner>>,
+    payloads: Payloads<T>,
+    sink: SharedSink<T>,
+    spec: Option<(PathBuf, SpecReload<T>)>,
+}
+
+// The directory the file is in and its name there.
+fn split(path: &Path) -> Option<(PathBuf, OsString)> {
+    let name = path.file_name()?.to_os_string();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    Some((dir, name))
+}
+
+// The path events for the file come in as.
+fn event_path(path: &Path) -> Option<PathBuf> {
+    split(path).map(|(dir, name)| dir.join(name))
+}
+
+fn shown_icon(inner: &Inner) -> Option<String> {
+    match inner.icon {
+        Some(ref icon) if icon.status == IconStatus::SHOW => match icon.resource {
+            IconResource::File(ref file) => Some(file.clone()),
+            IconResource::Resource(_) => None,
+        },
+        _ => None,
+    }
+}
+
+impl<T: Clone + Send + 'static> Watcher<T> {
+    fn run(mut self) {
+        let mut buffer = [0; 4096];
+        loop {
+            let icon = {
+                let inner = lock_inner(&self.inner);
+                if inner.closed {
+                    return;
+                }
+                shown_icon(&inner)
+            };
+            self.watch(icon.as_ref().map(Path::new));
+            match self.wait() {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(e) => {
+                    warn!("Hot reload stopped: {}", e);
+                    return;
+                }
+            }
+            let changed: Vec<PathBuf> = match self.inotify.read_events(&mut buffer) {
+                Ok(events) => {
+                    let dirs = &self.dirs;
+                    events.filter_map(|event| event.name.map(|name| (event.wd, name)))
+                        .flat_map(|(wd, name)| {
+                            dirs.iter()
+                                .filter(move |&(_, w)| *w == wd)
+                                .map(move |(dir, _)| dir.join(name))
+                        })
+                        .collect()
+                }
+                Err(e) => {
+                    warn!("Hot reload stopped: {}", e);
+                    return;
+                }
+            };
+            if let Some((ref path, reload)) = self.spec {
+                if event_path(path).map_or(false, |path| changed.contains(&path)) {
+                    let result = reload(&mut lock_inner(&self.inner), &self.payloads, path);
+                    self.send(result.map(|_| path.display().to_string()));
+                }
+            }
+            if let Some(icon) = icon {
+                if event_path(Path::new(&icon)).map_or(false, |path| changed.contains(&path)) {
+                    let result = {
+                        let inner = lock_inner(&self.inner);
+                        // Unless the spec just put up another one.
+                        if shown_icon(&inner).as_ref() == Some(&icon) {
+                            Some(inner.set_icon(&IconResource::File(icon.clone())))
+                        } else {
+                            None
+                        }
+                    };
+                    if let Some(result) = result {
+                        self.send(result.map(|_| icon.clone()).map_err(|e| format!("{}: {}", icon, e)));
+                    }
+                }
+            }
+        }
+    }
+
+    // Blocks until inotify has events or the tray woke us. False once the
+    // tray is gone.
+    fn wait(&mut self) -> io::Result<bool> {
+        let mut fds = [
+            libc::pollfd { fd: self.inotify.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: self.woken.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+        ];
+        loop {
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } >= 0 {
+                break;
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+        if fds[1].revents != 0 {
+            let mut buffer = [0; 64];
+            // Nothing to read means the tray dropped its end.
+            if self.woken.read(&mut buffer)? == 0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Watches the directories of the icon and spec and no others.
+    fn watch(&mut self, icon: Option<&Path>) {
+        let wanted: Vec<PathBuf> = icon.into_iter()
+            .chain(self.spec.as_ref().map(|&(ref path, _)| path.as_path()))
+            .filter_map(|path| split(path).map(|(dir, _)| dir))
+            .collect();
+        let gone: Vec<PathBuf> = self.dirs.keys().filter(|dir| !wanted.contains(dir)).cloned().collect();
+        for dir in gone {
+            let wd = self.dirs.remove(&dir).unwrap();
+            // Two paths for the same directory share their watch.
+            if !self.dirs.values().any(|w| *w == wd) {
+                self.inotify.rm_watch(wd).ok();
+            }
+        }
+        for dir in wanted {
+            if self.dirs.contains_key(&dir) {
+                continue;
+            }
+            // Missing directories are tried again next time round.
+            if let Ok(wd) = self.inotify.add_watch(&dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO) {
+                self.dirs.insert(dir, wd);
+            }
+        }
+    }
+
+    fn send(&self, result: Result<String, String>) {
+        let event = match result {
+            Ok(file) => SystrayEvent::Reloaded(file),
+            Err(msg) => SystrayEvent::ReloadFailed(msg),
+        };
+        // A closed sink is left to the event pump, which shuts the tray down
+        // on its next event and so stops this watcher too.
+        self.sink.lock().unwrap_or_else(|e| e.into_inner()).send_event(event).ok();
+    }
+}
@@ -0,0 +1,67 @@
+// Where the tray delivers its events. The event pump is the only caller, so a
+// sink only ever sees one thread at a time.
+
+use std::sync::mpsc::Sender;
+use {SystrayError, SystrayEvent};
+
+pub trait EventSink<T>: Send + 'static {
+    // An error means nobody is listening anymore and the tray shuts down.
+    fn send_event(&mut self, event: SystrayEvent<T>) -> Result<(), SystrayError>;
+}
+
+// The application's sink, and sinks that get copies of its events, like a
+// D-Bus service's. Only the application's sink hanging up counts, copies
+// that do are dropped.
+pub struct Sinks<T> {
+    pub sink: Box<dyn EventSink<T>>,
+    pub copies: Vec<Box<dyn EventSink<T>>>,
+}
+
+impl<T: Clone + 'static> Sinks<T> {
+    pub fn new(sink: Box<dyn EventSink<T>>) -> Sinks<T> {
+        Sinks {
+            sink: sink,
+            copies: Vec::new(),
+        }
+    }
+
+    pub fn send_event(&mut self, event: SystrayEvent<T>) -> Result<(), SystrayError> {
+        let mut copies = Vec::new();
+        for mut copy in self.copies.drain(..) {
+            if copy.send_event(event.clone()).is_ok() {
+                copies.push(copy);
+            }
+        }
+        self.copies = copies;
+        self.sink.send_event(event)
+    }
+}
+
+impl<T: Send + 'static> EventSink<T> for Sender<SystrayEvent<T>> {
+    fn send_event(&mut self, event: SystrayEvent<T>) -> Result<(), SystrayError> {
+        self.send(event).map_err(|_| SystrayError::EventSinkClosed)
+    }
+}
+
+// Closures can't hang up, they get every event until the tray quits.
+impl<T, F> EventSink<T> for F
+    where F: FnMut(SystrayEvent<T>) + Send + 'static {
+    fn send_event(&mut self, event: SystrayEvent<T>) -> Result<(), SystrayError> {
+        self(event);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "crossbeam-channel")]
+impl<T: Send + 'static> EventSink<T> for ::crossbeam_channel::Sender<SystrayEvent<T>> {
+    fn send_event(&mut self, event: SystrayEvent<T>) -> Result<(), SystrayError> {
+        self.send(event).map_err(|_| SystrayError::EventSinkClosed)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T: Send + 'static> EventSink<T> for ::futures::sync::mpsc::UnboundedSender<SystrayEvent<T>> {
+    fn send_event(&mut self, event: SystrayEvent<T>) -> Result<(), SystrayError> {
+        self.unbounded_send(event).map_err(|_| SystrayError::EventSinkClosed)
+    }
+}
@@ -0,0 +1,191 @@
+// Named looks for applications that move between a few states, say idle,
+// syncing and offline:
+//
+//     app.set_states(TrayStates::new()
+//         .state("idle", StateBundle::new()
+//             .icon(IconResource::Resource("network-idle".to_string()))
+//             .tooltip("Up to date")
+//             .enabled(sync_now, true))
+//         .state("syncing", StateBundle::new()
+//             .icon(IconResource::Resource("network-transmit".to_string()))
+//             .tooltip("Syncing")
+//             .enabled(sync_now, false))
+//         .state("offline", StateBundle::new()
+//             .tooltip("Offline")
+//             .attention_icon(IconResource::Resource("network-offline".to_string()))
+//             .attention(true)));
+//     app.set_state("syncing")?;
+//
+// A bundle only changes what it names, everything else stays the way the
+// last state or call left it.
+
+use std::collections::{BTreeMap, HashMap};
+use {Application, IconResource, IconStatus, Inner, SystrayError};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StateBundle {
+    icon: Option<IconResource>,
+    tooltip: Option<String>,
+    labels: BTreeMap<u32, String>,
+    enabled: BTreeMap<u32, bool>,
+    attention: Option<bool>,
+    attention_icon: Option<IconResource>,
+}
+
+impl StateBundle {
+    pub fn new() -> StateBundle {
+        StateBundle::default()
+    }
+
+    // Shown even if the icon was hidden.
+    pub fn icon(mut self, icon: IconResource) -> StateBundle {
+        self.icon = Some(icon);
+        self
+    }
+
+    pub fn tooltip(mut self, tooltip: &str) -> StateBundle {
+        self.tooltip = Some(tooltip.to_string());
+        self
+    }
+
+    pub fn label(mut self, item_idx: u32, item_name: &str) -> StateBundle {
+        self.labels.insert(item_idx, item_name.to_string());
+        self
+    }
+
+    pub fn enabled(mut self, item_idx: u32, enabled: bool) -> StateBundle {
+        self.enabled.insert(item_idx, enabled);
+        self
+    }
+
+    // Whether the host should make the icon stand out. Switching to a state
+    // with this fails with NotImplementedError on backends without
+    // Capabilities::attention.
+    pub fn attention(mut self, attention: bool) -> StateBundle {
+        self.attention = Some(attention);
+        self
+    }
+
+    // What the icon looks like while it asks for attention, from this state
+    // on. Needs Capabilities::attention too.
+    pub fn attention_icon(mut self, icon: IconResource) -> StateBundle {
+        self.attention_icon = Some(icon);
+        self
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrayStates {
+    states: HashMap<String, StateBundle>,
+}
+
+impl TrayStates {
+    pub fn new() -> TrayStates {
+        TrayStates::default()
+    }
+
+    // Replaces any state of the same name.
+    pub fn state(mut self, name: &str, bundle: StateBundle) -> TrayStates {
+        self.states.insert(name.to_string(), bundle);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&StateBundle> {
+        self.states.get(name)
+    }
+}
+
+impl Inner {
+    pub fn set_state(&mut self, name: &str) -> Result<(), SystrayError> {
+        let bundle = match self.states.get(name) {
+            Some(bundle) => bundle.clone(),
+            None => return Err(SystrayError::UnknownState(name.to_string())),
+        };
+        // Before anything changes, so a stale item index doesn't leave the
+        // tray half way between states.
+        for idx in bundle.labels.keys().chain(bundle.enabled.keys()) {
+            self.menu_item_pos(*idx)?;
+        }
+        let wants_attention = bundle.attention.is_some() || bundle.attention_icon.is_some();
+        if wants_attention && !self.window.capabilities().attention {
+            return Err(SystrayError::NotImplementedError);
+        }
+        // What the bundle is about to change, to go back to should the
+        // backend fail half way.
+        let mut undo = StateBundle {
+            tooltip: bundle.tooltip.as_ref().map(|_| self.tooltip.clone()),
+            attention: bundle.attention.map(|_| self.attention),
+            attention_icon: bundle.attention_icon.as_ref().and(self.attention_icon.clone()),
+            ..StateBundle::default()
+        };
+        for idx in bundle.labels.keys().chain(bundle.enabled.keys()) {
+            let (_, _, label, enabled, _) = self.menu_item(*idx)?;
+            undo.labels.insert(*idx, label);
+            undo.enabled.insert(*idx, enabled);
+        }
+        let icon = self.icon.clone();
+        if let Err(e) = self.apply(bundle) {
+            let _ = self.apply(undo);
+            match icon {
+                Some(ref icon) if icon.status == IconStatus::SHOW => {
+                    let _ = self.show_icon(icon.resource.clone());
+                }
+                _ => {
+                    if self.icon != icon {
+                        let _ = self.window.delete_icon();
+                        self.icon = icon;
+                    }
+                }
+            }
+            self.changed();
+            return Err(e);
+        }
+        self.current_state = Some(name.to_string());
+        self.changed();
+        Ok(())
+    }
+
+    fn apply(&mut self, bundle: StateBundle) -> Result<(), SystrayError> {
+        if let Some(tooltip) = bundle.tooltip {
+            self.set_tooltip(tooltip)?;
+        }
+        if let Some(icon) = bundle.icon {
+            self.show_icon(icon)?;
+        }
+        for (idx, label) in bundle.labels {
+            self.set_menu_item_label(idx, &label)?;
+        }
+        for (idx, enabled) in bundle.enabled {
+            self.set_menu_item_enabled(idx, enabled)?;
+        }
+        if let Some(icon) = bundle.attention_icon {
+            self.window.set_attention_icon(&icon)?;
+            self.attention_icon = Some(icon);
+        }
+        if let Some(attention) = bundle.attention {
+            self.window.set_attention(attention)?;
+            self.attention = attention;
+        }
+        Ok(())
+    }
+}
+
+impl<T> Application<T> {
+    // The states set_state can switch to. The tray keeps its current look.
+    pub fn set_states(&mut self, states: TrayStates) {
+        self.inner().states = states;
+    }
+
+    // Applies the whole bundle in one go, commands sent through a TrayHandle
+    // meanwhile run before or after it. Should a backend call fail half way
+    // what was changed already is put back, as far as the backend lets us,
+    // and the current state stays the old one.
+    pub fn set_state(&mut self, name: &str) -> Result<(), SystrayError> {
+        self.inner().set_state(name)
+    }
+
+    // Name of the state last applied.
+    pub fn current_state(&self) -> Option<String> {
+        self.inner().current_state.clone()
+    }
+}
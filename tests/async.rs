@@ -0,0 +1,33 @@
+#![cfg(feature = "async")]
+
+extern crate futures;
+extern crate systray;
+
+use futures::{Future, Stream};
+use systray::{Application, Backend, IconResource, MockTray, SystrayEvent};
+
+#[test]
+fn events_stream_and_async_replies() {
+    let mock = MockTray::new();
+    let mut app = Application::builder()
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    let events = app.events();
+    let handle = app.handle();
+
+    let idx = handle.add_menu_item("Item".to_string())
+        .join(handle.show_icon(IconResource::Resource("dialog-information".to_string())))
+        .wait()
+        .unwrap()
+        .0;
+    assert!(mock.is_visible());
+
+    assert!(mock.click_menu_item(idx));
+    app.quit();
+    let events: Vec<SystrayEvent> = events.collect().wait().unwrap();
+    match events[..] {
+        [SystrayEvent::MenuItemClick(i), SystrayEvent::Quit] => assert_eq!(i, idx),
+        _ => panic!("unexpected events"),
+    }
+}
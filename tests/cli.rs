@@ -0,0 +1,77 @@
+#![cfg(feature = "cli")]
+
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+fn spec_file(name: &str, contents: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("systray-cli-{}-{}", std::process::id(), name));
+    File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+    path
+}
+
+fn run_with_spec(name: &str, contents: &str) -> (Option<i32>, String) {
+    let path = spec_file(name, contents);
+    let out = Command::new(env!("CARGO_BIN_EXE_systray")).arg(&path).output().unwrap();
+    std::fs::remove_file(&path).ok();
+    (out.status.code(), String::from_utf8_lossy(&out.stderr).into_owned())
+}
+
+#[test]
+fn cli_rejects_bad_specs() {
+    let (code, err) = run_with_spec("dup.toml", r#"
+        [[menu]]
+        id = "a"
+        label = "One"
+        [[menu]]
+        label = "More"
+        submenu = [ { id = "a", label = "Two" } ]
+    "#);
+    assert_eq!(code, Some(1));
+    assert!(err.contains("\"a\" is used twice"), "{}", err);
+
+    let (code, err) = run_with_spec("sep.json", r#"{"menu": [{"separator": true, "label": "x"}]}"#);
+    assert_eq!(code, Some(1));
+    assert!(err.contains("separator"), "{}", err);
+
+    let (code, err) = run_with_spec("typo.toml", "tooltp = \"x\"\n");
+    assert_eq!(code, Some(1));
+    assert!(err.contains("tooltp"), "{}", err);
+}
+
+#[test]
+fn cli_needs_a_spec() {
+    let out = Command::new(env!("CARGO_BIN_EXE_systray")).arg("--bogus").output().unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("usage"));
+
+    let out = Command::new(env!("CARGO_BIN_EXE_systray")).arg("/nonexistent/spec.toml").output().unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("cannot read"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn cli_speaks_json_rpc() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_systray"))
+        .arg("--json-rpc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    {
+        let stdin = child.stdin.as_mut().unwrap();
+        writeln!(stdin, r#"{{"jsonrpc": "2.0", "id": 1, "method": "hello", "params": {{"version": 1}}}}"#).unwrap();
+        writeln!(stdin, r#"{{"jsonrpc": "2.0", "id": 2, "method": "quit"}}"#).unwrap();
+    }
+    let lines: Vec<String> = BufReader::new(child.stdout.take().unwrap()).lines()
+        .map(|l| l.unwrap())
+        .collect();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(lines.len(), 3, "{:?}", lines);
+    assert!(lines[0].contains(r#""id":1"#) && lines[0].contains(r#""capabilities""#), "{}", lines[0]);
+    assert_eq!(lines[1], r#"{"id":2,"jsonrpc":"2.0","result":null}"#);
+    assert!(lines[2].contains(r#""type":"quit""#), "{}", lines[2]);
+}
@@ -0,0 +1,185 @@
+#![cfg(feature = "config")]
+
+extern crate systray;
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+use systray::{Backend, Builder, Capabilities, IconResource, MockMenuEntry, MockTray, SystrayError, SystrayEvent};
+use systray::config::{Format, ItemKind, TraySpec};
+
+const TOML: &'static str = r#"
+id = "org.example.Backup"
+icon = "network-idle"
+tooltip = "Backup"
+
+[[menu]]
+id = "run"
+label = "Run now"
+
+[[menu]]
+id = "pause"
+label = "Paused"
+kind = "check"
+checked = true
+
+[[menu]]
+kind = "separator"
+
+[[menu]]
+label = "Schedule"
+submenu = [ { id = "daily", label = "Daily", kind = "radio", checked = true },
+            { id = "never", label = "Never", kind = "radio", enabled = false } ]
+"#;
+
+const YAML: &'static str = "
+id: org.example.Backup
+icon: network-idle
+tooltip: Backup
+menu:
+  - id: run
+    label: Run now
+  - id: pause
+    label: Paused
+    kind: check
+    checked: true
+  - kind: separator
+  - label: Schedule
+    submenu:
+      - {id: daily, label: Daily, kind: radio, checked: true}
+      - {id: never, label: Never, kind: radio, enabled: false}
+";
+
+fn checkable_mock() -> MockTray {
+    let mock = MockTray::new();
+    mock.set_capabilities(Capabilities { submenus: true, check_items: true, ..Capabilities::default() });
+    mock
+}
+
+fn error(text: &str, format: Format) -> (Option<usize>, String) {
+    let e = TraySpec::parse(text, format).unwrap_err();
+    (e.line(), e.message().to_string())
+}
+
+#[test]
+fn config_formats_agree() {
+    let spec = TraySpec::parse(TOML, Format::Toml).unwrap();
+    assert_eq!(TraySpec::parse(YAML, Format::Yaml).unwrap(), spec);
+    let json = r#"{"id": "org.example.Backup", "icon": "network-idle", "tooltip": "Backup", "menu": [
+        {"id": "run", "label": "Run now"},
+        {"id": "pause", "label": "Paused", "kind": "check", "checked": true},
+        {"kind": "separator"},
+        {"label": "Schedule", "submenu": [
+            {"id": "daily", "label": "Daily", "kind": "radio", "checked": true},
+            {"id": "never", "label": "Never", "kind": "radio", "enabled": false}]}]}"#;
+    let from_json = TraySpec::parse(json, Format::Json).unwrap();
+    assert_eq!(from_json.menu[2].kind(), ItemKind::Separator);
+    assert_eq!(from_json.menu[3].kind(), ItemKind::Submenu);
+    assert_eq!(from_json.menu[3].submenu, spec.menu[3].submenu);
+}
+
+#[test]
+fn config_builds_tray() {
+    let mock = checkable_mock();
+    let (tx, rx) = channel();
+    let spec = TraySpec::parse(TOML, Format::Toml).unwrap();
+    let mut app = spec.build(Builder::new().backend(Backend::Mock(mock.clone())).event_sink(tx)).unwrap();
+    assert_eq!(mock.id(), "org.example.Backup");
+    assert_eq!(mock.icon(), Some(IconResource::Resource("network-idle".to_string())));
+    assert_eq!(mock.tooltip(), "Backup");
+    assert_eq!(mock.menu(), vec![MockMenuEntry::Item(0, "Run now".to_string()),
+                                 MockMenuEntry::Check(1, "Paused".to_string(), true),
+                                 MockMenuEntry::Separator(2),
+                                 MockMenuEntry::Submenu(3, "Schedule".to_string(), vec![
+                                     MockMenuEntry::Radio(4, "Daily".to_string(), true),
+                                     MockMenuEntry::Radio(5, "Never".to_string(), false)])]);
+    assert!(mock.is_enabled(4));
+    assert!(!mock.is_enabled(5));
+
+    assert!(mock.click_menu_item(4));
+    match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+        SystrayEvent::MenuItemClick(id) => assert_eq!(id, "daily"),
+        _ => panic!("expected a click"),
+    }
+    app.quit();
+}
+
+#[test]
+fn config_needs_backends_with_check_items_and_submenus() {
+    let spec = TraySpec::parse(TOML, Format::Toml).unwrap();
+    match spec.build(Builder::new().backend(Backend::Mock(MockTray::new()))) {
+        Err(SystrayError::NotImplementedError) => {}
+        Err(e) => panic!("wrong error: {}", e),
+        Ok(_) => panic!("built a check item on a backend without them"),
+    }
+}
+
+#[test]
+fn config_errors_have_lines() {
+    let (line, msg) = error(&TOML.replace("\"daily\"", "\"run\""), Format::Toml);
+    assert_eq!(msg, "menu item id \"run\" is used twice");
+    assert_eq!(line, Some(21));
+    let (line, msg) = error(&YAML.replace("checked: true}", "checked: true, kind: radio}"), Format::Yaml);
+    assert_eq!(line, Some(15));
+    assert!(msg.contains("duplicate field"), "{}", msg);
+    let (line, msg) = error(&YAML.replace("enabled: false", "checked: true"), Format::Yaml);
+    assert_eq!(line, Some(16));
+    assert!(msg.contains("second checked radio item"), "{}", msg);
+    let (line, msg) = error("{\"menu\": [\n{\"label\": \"Run\"}\n]}", Format::Json);
+    assert_eq!((line, &msg[..]), (Some(2), "menu item \"Run\" has no id"));
+    let (line, msg) = error("tooltip = \"Backup\"\nicon = 3\n", Format::Toml);
+    assert_eq!(line, Some(2));
+    assert!(msg.contains("invalid type"), "{}", msg);
+    let (_, msg) = error("[[menu]]\nlabel = \"Empty\"\nkind = \"submenu\"\n", Format::Toml);
+    assert_eq!(msg, "submenu \"Empty\" is empty");
+    let (_, msg) = error("[[menu]]\nid = \"a\"\nlabel = \"A\"\nchecked = true\n", Format::Toml);
+    assert_eq!(msg, "menu item \"A\" can't be checked");
+
+    // Only id keys count when looking for an id, not another item's label.
+    let toml = "[[menu]]\nid = \"go\"\nlabel = \"run\"\n\n[[menu]]\nid = \"run\"\nlabel = \"Run\"\n\n\
+                [[menu]]\nid = \"run\"\nlabel = \"Again\"\n";
+    let (line, msg) = error(toml, Format::Toml);
+    assert_eq!((line, &msg[..]), (Some(10), "menu item id \"run\" is used twice"));
+    let yaml = "# id: run\nmenu:\n  - label: run\n    id: go\n  - {label: Go, id: run}\n  - {label: Again, id: run}\n";
+    let (line, _) = error(yaml, Format::Yaml);
+    assert_eq!(line, Some(6));
+
+    let path = env::temp_dir().join(format!("systray-config-{}.yml", std::process::id()));
+    File::create(&path).unwrap().write_all(b"menu:\n  - label: Run\n").unwrap();
+    let e = TraySpec::load(&path).unwrap_err();
+    std::fs::remove_file(&path).ok();
+    assert_eq!(e.to_string(), format!("{}:2: menu item \"Run\" has no id", path.display()));
+}
+
+#[test]
+fn config_applies_changes_in_place() {
+    let mock = checkable_mock();
+    let spec = TraySpec::parse(TOML, Format::Toml).unwrap();
+    let mut app = spec.build(Builder::new().backend(Backend::Mock(mock.clone()))).unwrap();
+    let changed = TOML.replace("Run now", "Run again")
+        .replace("checked = true\n", "checked = true\nenabled = false\n")
+        .replace("kind = \"separator\"", "id = \"stop\"\nlabel = \"Stop\"")
+        .replace("tooltip = \"Backup\"", "tooltip = \"Backup paused\"");
+    app.apply_config(&TraySpec::parse(&changed, Format::Toml).unwrap()).unwrap();
+    assert_eq!(mock.tooltip(), "Backup paused");
+    // The first two keep their place, the rest is new from the separator on.
+    assert_eq!(mock.menu(), vec![MockMenuEntry::Item(0, "Run again".to_string()),
+                                 MockMenuEntry::Check(1, "Paused".to_string(), true),
+                                 MockMenuEntry::Item(6, "Stop".to_string()),
+                                 MockMenuEntry::Submenu(7, "Schedule".to_string(), vec![
+                                     MockMenuEntry::Radio(8, "Daily".to_string(), true),
+                                     MockMenuEntry::Radio(9, "Never".to_string(), false)])]);
+    assert!(!mock.is_enabled(1));
+    assert!(!mock.is_enabled(9));
+    assert_eq!(app.find_menu_item(&"stop".to_string()), Some(6));
+    assert_eq!(app.find_menu_item(&"daily".to_string()), Some(8));
+
+    app.apply_config(&spec).unwrap();
+    assert_eq!(mock.menu()[..2], [MockMenuEntry::Item(0, "Run now".to_string()),
+                                  MockMenuEntry::Check(1, "Paused".to_string(), true)]);
+    assert_eq!(mock.menu()[2], MockMenuEntry::Separator(10));
+    assert!(mock.is_enabled(1));
+    app.quit();
+}
@@ -0,0 +1,111 @@
+#![cfg(unix)]
+
+extern crate systray;
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use systray::{Application, Backend, Builder, IconResource, Menu, MockMenuEntry, MockTray};
+use systray::control::{ControlClient, ControlError};
+use systray::protocol::Command;
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("systray-{}-{}.sock", name, std::process::id()));
+    fs::remove_file(&path).ok();
+    path
+}
+
+fn mock_app(mock: &MockTray) -> Application<String> {
+    Builder::new()
+        .menu(Menu::new().item_with("Run now", "run".to_string()))
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn control_client_drives_tray() {
+    let mock = MockTray::new();
+    let mut app = mock_app(&mock);
+    let path = socket_path("client");
+    let server = app.serve_control(&path).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+    let mut client = ControlClient::connect(&path).unwrap();
+    let commands = [Command::Icon("network-idle".to_string()),
+                    Command::Tooltip("Backup running".to_string()),
+                    Command::Separator,
+                    Command::AddItem("stop".to_string(), "Stop it".to_string()),
+                    Command::SetEnabled("run".to_string(), false),
+                    Command::Notify("Backup".to_string(), "Started".to_string())];
+    for command in commands.iter() {
+        assert_eq!(client.run(command).unwrap(), Vec::<String>::new());
+    }
+    assert_eq!(mock.icon(), Some(IconResource::Resource("network-idle".to_string())));
+    assert_eq!(mock.tooltip(), "Backup running");
+    assert_eq!(mock.menu(), vec![MockMenuEntry::Item(0, "Run now".to_string()),
+                                 MockMenuEntry::Separator(1),
+                                 MockMenuEntry::Item(2, "Stop it".to_string())]);
+    assert!(!mock.is_enabled(0));
+    assert_eq!(mock.notifications(), vec![("Backup".to_string(), "Started".to_string())]);
+
+    // A second client sees what the first one did.
+    let mut other = ControlClient::connect(&path).unwrap();
+    assert_eq!(other.state().unwrap(),
+               vec!["state icon resource network-idle", "state visible true",
+                    "state tooltip \"Backup running\"", "state label \"\"",
+                    "state item run false \"Run now\"", "state separator",
+                    "state item stop true \"Stop it\""]);
+    match other.run(&Command::SetEnabled("nope".to_string(), true)) {
+        Err(ControlError::Remote(msg)) => assert_eq!(msg, "no menu item with id nope"),
+        other => panic!("unknown id accepted: {:?}", other),
+    }
+    // Items the app adds itself are known to the socket too.
+    app.add_menu_item_with(&"Later".to_string(), "later".to_string()).unwrap();
+    client.run(&Command::SetEnabled("later".to_string(), false)).unwrap();
+    assert!(!mock.is_enabled(3));
+
+    // Dropping the server hangs up on the clients still connected.
+    drop(server);
+    assert!(!path.exists());
+    assert!(ControlClient::connect(&path).is_err());
+    match client.state() {
+        Err(ControlError::Io(_)) => {}
+        other => panic!("connection outlived the server: {:?}", other),
+    }
+    app.quit();
+}
+
+#[test]
+fn control_rejects_malformed_lines() {
+    let mock = MockTray::new();
+    let mut app = mock_app(&mock);
+    let path = socket_path("malformed");
+    let _server = app.serve_control(&path).unwrap();
+    assert!(app.serve_control(&path).is_err());
+    // Nothing is left of the directory the socket was bound in.
+    let name = format!(".{}.", path.file_name().unwrap().to_string_lossy());
+    assert!(fs::read_dir(env::temp_dir()).unwrap()
+        .all(|entry| !entry.unwrap().file_name().to_string_lossy().starts_with(&name)));
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut reply = || {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        line
+    };
+    stream.write_all(b"frobnicate\n").unwrap();
+    assert_eq!(reply(), "error \"unknown command frobnicate\"\n");
+    stream.write_all(b"tooltip \xff\xfe\n").unwrap();
+    assert_eq!(reply(), "error \"line is not UTF-8\"\n");
+    stream.write_all(&vec![b'x'; 100 * 1024]).unwrap();
+    stream.write_all(b"\n\nlabel 3\r\n").unwrap();
+    assert_eq!(reply(), "error \"line is too long\"\n");
+    assert_eq!(reply(), "ok\n");
+    assert_eq!(mock.label(), "3");
+    app.quit();
+}
@@ -0,0 +1,234 @@
+#![cfg(feature = "dbus-service")]
+
+extern crate dbus;
+#[cfg(feature = "async")]
+extern crate futures;
+extern crate systray;
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+use dbus::Message;
+use dbus::blocking::{Connection, Proxy};
+use dbus::blocking::stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged};
+use dbus::channel::Channel;
+use dbus::message::{MatchRule, SignalArgs};
+use systray::{Backend, Builder, IconResource, Menu, MockTray, StateBundle, SystrayEvent, TrayStates};
+use systray::dbus_service::{INTERFACE, PATH};
+
+const NAME: &'static str = "org.systrayrs.Test";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// A bus of our own, so the tests neither need nor disturb a session bus.
+struct Bus {
+    daemon: Child,
+    address: String,
+}
+
+impl Bus {
+    fn start() -> Bus {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(&["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("dbus-daemon needs to be installed for these tests");
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+        Bus {
+            daemon: daemon,
+            address: address.trim().to_string(),
+        }
+    }
+
+    fn connect(&self) -> Connection {
+        let mut channel = Channel::open_private(&self.address).unwrap();
+        channel.register().unwrap();
+        Connection::from(channel)
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        self.daemon.kill().ok();
+        self.daemon.wait().ok();
+    }
+}
+
+fn tray<'a>(conn: &'a Connection) -> Proxy<'a, &'a Connection> {
+    conn.with_proxy(NAME, PATH, TIMEOUT)
+}
+
+#[test]
+fn dbus_methods_and_properties() {
+    let bus = Bus::start();
+    let mock = MockTray::new();
+    let mut app = Builder::new()
+        .menu(Menu::new().item_with("Run now", "run".to_string()))
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    let service = app.serve_dbus_at(&bus.address, NAME).unwrap();
+    assert!(app.serve_dbus_at(&bus.address, NAME).is_err());
+
+    let conn = bus.connect();
+    let tray = tray(&conn);
+    tray.method_call::<(), _, _, _>(INTERFACE, "SetIcon", ("network-idle",)).unwrap();
+    tray.method_call::<(), _, _, _>(INTERFACE, "SetTooltip", ("Backup running",)).unwrap();
+    tray.method_call::<(), _, _, _>(INTERFACE, "AddMenuSeparator", ()).unwrap();
+    tray.method_call::<(), _, _, _>(INTERFACE, "AddMenuItem", ("stop", "Stop")).unwrap();
+    tray.method_call::<(), _, _, _>(INTERFACE, "SetMenuItemEnabled", ("run", false)).unwrap();
+    tray.method_call::<(), _, _, _>(INTERFACE, "Notify", ("Backup", "Started")).unwrap();
+    assert_eq!(mock.icon(), Some(IconResource::Resource("network-idle".to_string())));
+    assert_eq!(mock.tooltip(), "Backup running");
+    assert!(!mock.is_enabled(0));
+    assert_eq!(mock.notifications(), vec![("Backup".to_string(), "Started".to_string())]);
+
+    app.set_states(TrayStates::new().state("idle", StateBundle::new().tooltip("Idle")));
+    tray.method_call::<(), _, _, _>(INTERFACE, "SetState", ("idle",)).unwrap();
+    assert_eq!(mock.tooltip(), "Idle");
+    let err = tray.method_call::<(), _, _, _>(INTERFACE, "SetState", ("busy",)).unwrap_err();
+    assert_eq!(err.name(), Some("org.systrayrs.Tray1.Error.Tray"));
+    tray.method_call::<(), _, _, _>(INTERFACE, "SetTooltip", ("Backup running",)).unwrap();
+
+    let err = tray.method_call::<(), _, _, _>(INTERFACE, "SetMenuItemEnabled", ("nope", true)).unwrap_err();
+    assert_eq!(err.name(), Some("org.systrayrs.Tray1.Error.UnknownId"));
+    let err = tray.method_call::<(), _, _, _>(INTERFACE, "AddMenuItem", ("stop", "Again")).unwrap_err();
+    assert_eq!(err.name(), Some("org.systrayrs.Tray1.Error.DuplicateId"));
+
+    let icon: String = tray.get(INTERFACE, "Icon").unwrap();
+    assert_eq!(icon, "network-idle");
+    let tooltip: String = tray.get(INTERFACE, "Tooltip").unwrap();
+    assert_eq!(tooltip, "Backup running");
+    let visible: bool = tray.get(INTERFACE, "Visible").unwrap();
+    assert!(visible);
+    tray.method_call::<(), _, _, _>(INTERFACE, "SetMenuItemLabel", ("stop", "Stop it")).unwrap();
+    let state: String = tray.get(INTERFACE, "State").unwrap();
+    assert_eq!(state, "idle");
+    let menu: Vec<(String, String, String, bool, bool)> = tray.get(INTERFACE, "Menu").unwrap();
+    assert_eq!(menu, vec![("item".to_string(), "run".to_string(), "Run now".to_string(), false, false),
+                          ("separator".to_string(), "".to_string(), "".to_string(), false, false),
+                          ("item".to_string(), "stop".to_string(), "Stop it".to_string(), true, false)]);
+
+    drop(service);
+    assert!(tray.method_call::<(), _, _, _>(INTERFACE, "HideIcon", ()).is_err());
+    assert!(mock.is_visible());
+    app.quit();
+}
+
+#[test]
+fn dbus_signals_mirror_events() {
+    let bus = Bus::start();
+    let mock = MockTray::new();
+    let (tx, rx) = channel();
+    let app = Builder::new()
+        .menu(Menu::new().item_with("Open", "open".to_string()))
+        .backend(Backend::Mock(mock.clone()))
+        .event_sink(tx)
+        .build()
+        .unwrap();
+    let _service = app.serve_dbus_at(&bus.address, NAME).unwrap();
+
+    let conn = bus.connect();
+    let signals = Arc::new(Mutex::new(Vec::new()));
+    let seen = signals.clone();
+    conn.add_match(MatchRule::new_signal(INTERFACE, "MenuItemClick").static_clone(), move |(): (), _, msg: &Message| {
+        seen.lock().unwrap().push((msg.member().unwrap().to_string(), msg.get1::<String>()));
+        true
+    }).unwrap();
+    let seen = signals.clone();
+    conn.add_match(MatchRule::new_signal(INTERFACE, "Quit").static_clone(), move |(): (), _, msg: &Message| {
+        seen.lock().unwrap().push((msg.member().unwrap().to_string(), None));
+        true
+    }).unwrap();
+
+    assert!(mock.click_menu_item(0));
+    // The application's own sink still gets the event.
+    match rx.recv_timeout(TIMEOUT).unwrap() {
+        SystrayEvent::MenuItemClick(id) => assert_eq!(id, "open"),
+        _ => panic!("expected a click"),
+    }
+    // Quit over the bus, which answers before the tray is gone.
+    tray(&conn).method_call::<(), _, _, _>(INTERFACE, "Quit", ()).unwrap();
+
+    let start = Instant::now();
+    while signals.lock().unwrap().len() < 2 && start.elapsed() < TIMEOUT {
+        conn.process(Duration::from_millis(50)).unwrap();
+    }
+    assert_eq!(*signals.lock().unwrap(), vec![("MenuItemClick".to_string(), Some("open".to_string())),
+                                             ("Quit".to_string(), None)]);
+}
+
+// Taking the events as a stream swaps the application's sink, the service
+// keeps its copies.
+#[cfg(feature = "async")]
+#[test]
+fn dbus_signals_outlive_events() {
+    use futures::Stream;
+
+    let bus = Bus::start();
+    let mock = MockTray::new();
+    let mut app = Builder::new()
+        .menu(Menu::new().item_with("Open", "open".to_string()))
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    let _service = app.serve_dbus_at(&bus.address, NAME).unwrap();
+    let mut events = app.events().wait();
+
+    let conn = bus.connect();
+    let clicks = Arc::new(Mutex::new(Vec::new()));
+    let seen = clicks.clone();
+    conn.add_match(MatchRule::new_signal(INTERFACE, "MenuItemClick").static_clone(), move |(): (), _, msg: &Message| {
+        seen.lock().unwrap().push(msg.get1::<String>());
+        true
+    }).unwrap();
+
+    assert!(mock.click_menu_item(0));
+    match events.next() {
+        Some(Ok(SystrayEvent::MenuItemClick(id))) => assert_eq!(id, "open"),
+        _ => panic!("expected a click"),
+    }
+    let start = Instant::now();
+    while clicks.lock().unwrap().is_empty() && start.elapsed() < TIMEOUT {
+        conn.process(Duration::from_millis(50)).unwrap();
+    }
+    assert_eq!(*clicks.lock().unwrap(), vec![Some("open".to_string())]);
+    app.quit();
+}
+
+#[test]
+fn dbus_properties_announce_changes() {
+    let bus = Bus::start();
+    let mock = MockTray::new();
+    let mut app = Builder::new()
+        .menu(Menu::new().item_with("Open", "open".to_string()))
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    let _service = app.serve_dbus_at(&bus.address, NAME).unwrap();
+
+    let conn = bus.connect();
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let seen = changes.clone();
+    conn.add_match(PropertiesPropertiesChanged::match_rule(None, None).static_clone(),
+                   move |change: PropertiesPropertiesChanged, _: &Connection, _: &Message| {
+        assert_eq!(change.interface_name, INTERFACE);
+        let mut names: Vec<String> = change.changed_properties.keys().cloned().collect();
+        names.sort();
+        let tooltip = change.changed_properties.get("Tooltip").and_then(|v| v.0.as_str().map(String::from));
+        seen.lock().unwrap().push((names, tooltip));
+        true
+    }).unwrap();
+
+    // Changes made by the application itself count as well.
+    app.set_tooltip("Backup running".to_string()).unwrap();
+    let start = Instant::now();
+    while changes.lock().unwrap().is_empty() && start.elapsed() < TIMEOUT {
+        conn.process(Duration::from_millis(50)).unwrap();
+    }
+    assert_eq!(*changes.lock().unwrap(), vec![(vec!["Tooltip".to_string()], Some("Backup running".to_string()))]);
+    app.quit();
+}
@@ -0,0 +1,214 @@
+#![cfg(feature = "json-rpc")]
+
+extern crate serde_json;
+extern crate systray;
+
+use serde_json::Value;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+use systray::{Application, Backend, Builder, IconResource, MockMenuEntry, MockTray, StateBundle, SystrayEvent,
+              TrayStates};
+use systray::json_rpc::{JsonRpc, event_notification};
+
+struct Client {
+    mock: MockTray,
+    app: Application<String>,
+    rpc: JsonRpc,
+    events: Receiver<SystrayEvent<String>>,
+    notifications: Receiver<String>,
+    next_id: u64,
+}
+
+impl Client {
+    fn new() -> Client {
+        let mock = MockTray::new();
+        let (tx, events) = channel();
+        let app = Builder::new()
+            .backend(Backend::Mock(mock.clone()))
+            .event_sink(tx)
+            .build()
+            .unwrap();
+        let (tx, notifications) = channel();
+        Client {
+            mock: mock,
+            app: app,
+            rpc: JsonRpc::new(tx),
+            events: events,
+            notifications: notifications,
+            next_id: 0,
+        }
+    }
+
+    fn send(&mut self, line: &str) -> Option<Value> {
+        self.rpc.handle_line(&mut self.app, line)
+            .map(|reply| serde_json::from_str(&reply).unwrap())
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let request = json_request(self.next_id, method, params);
+        let reply = self.send(&request).expect("no response");
+        assert_eq!(reply["jsonrpc"], "2.0");
+        assert_eq!(reply["id"], self.next_id);
+        reply
+    }
+
+    fn ok(&mut self, method: &str, params: Value) -> Value {
+        let reply = self.call(method, params);
+        assert!(reply.get("error").is_none(), "{} failed: {}", method, reply);
+        reply["result"].clone()
+    }
+
+    fn error(&mut self, method: &str, params: Value) -> i64 {
+        let reply = self.call(method, params);
+        reply["error"]["code"].as_i64().expect("expected an error")
+    }
+
+    fn hello(&mut self) -> Value {
+        self.ok("hello", serde_json::from_str(r#"{"version": 1}"#).unwrap())
+    }
+}
+
+fn json_request(id: u64, method: &str, params: Value) -> String {
+    format!(r#"{{"jsonrpc": "2.0", "id": {}, "method": "{}", "params": {}}}"#, id, method, params)
+}
+
+fn params(json: &str) -> Value {
+    serde_json::from_str(json).unwrap()
+}
+
+#[test]
+fn json_rpc_handshake() {
+    let mut client = Client::new();
+    assert_eq!(client.error("set_tooltip", params(r#"{"tooltip": "early"}"#)), -32001);
+    assert_eq!(client.error("hello", params(r#"{"version": 99}"#)), -32002);
+    let reply = client.call("hello", params(r#"{"version": 99}"#));
+    assert_eq!(reply["error"]["data"]["supported"][0], 1);
+
+    let hello = client.hello();
+    assert_eq!(hello["version"], 1);
+    assert!(hello["server"].as_str().unwrap().starts_with("systray-rs "));
+    assert_eq!(hello["capabilities"]["label"], true);
+    assert_eq!(hello["capabilities"]["submenus"], false);
+    assert_eq!(client.ok("capabilities", Value::Null), hello["capabilities"]);
+}
+
+#[test]
+fn json_rpc_covers_the_application() {
+    let mut client = Client::new();
+    client.hello();
+    client.ok("show_icon", params(r#"{"resource": "mail-unread"}"#));
+    client.ok("set_tooltip", params(r#"{"tooltip": "Two new"}"#));
+    client.ok("set_label", params(r#"{"label": "2", "guide": "99"}"#));
+    client.ok("add_menu_item", params(r#"{"id": "open", "label": "Open"}"#));
+    client.ok("add_menu_separator", Value::Null);
+    client.ok("add_menu_item", params(r#"{"id": "quit", "label": "Quit"}"#));
+    client.ok("set_menu_item_label", params(r#"{"id": "open", "label": "Open inbox"}"#));
+    client.ok("set_menu_item_enabled", params(r#"{"id": "quit", "enabled": false}"#));
+    client.ok("notify", params(r#"{"title": "Mail", "body": "You have mail"}"#));
+
+    let mock = client.mock.clone();
+    assert_eq!(mock.icon(), Some(IconResource::Resource("mail-unread".to_string())));
+    assert_eq!(mock.tooltip(), "Two new");
+    assert_eq!((mock.label(), mock.label_guide()), ("2".to_string(), "99".to_string()));
+    assert_eq!(mock.menu(), vec![MockMenuEntry::Item(0, "Open inbox".to_string()),
+                                 MockMenuEntry::Separator(1),
+                                 MockMenuEntry::Item(2, "Quit".to_string())]);
+    assert!(!mock.is_enabled(2));
+    assert_eq!(mock.notifications(), vec![("Mail".to_string(), "You have mail".to_string())]);
+
+    assert_eq!(client.ok("state", Value::Null), params(r#"{
+        "icon": {"resource": "mail-unread"}, "visible": true,
+        "tooltip": "Two new", "label": "2", "guide": "99",
+        "menu": [{"type": "item", "id": "open", "label": "Open inbox", "enabled": true},
+                 {"type": "separator"},
+                 {"type": "item", "id": "quit", "label": "Quit", "enabled": false}],
+        "current_state": null}"#));
+
+    client.ok("hide_icon", Value::Null);
+    assert!(!mock.is_visible());
+    assert_eq!(client.ok("state", Value::Null)["visible"], false);
+
+    client.app.set_states(TrayStates::new().state("busy", StateBundle::new().tooltip("Busy")));
+    client.ok("set_state", params(r#"{"name": "busy"}"#));
+    assert_eq!(mock.tooltip(), "Busy");
+    assert_eq!(client.ok("state", Value::Null)["current_state"], "busy");
+    assert_eq!(client.error("set_state", params(r#"{"name": "idle"}"#)), -32000);
+
+    assert!(mock.click_menu_item(0));
+    let event = client.events.recv_timeout(Duration::from_secs(5)).unwrap();
+    let line: Value = serde_json::from_str(&event_notification(&event)).unwrap();
+    assert_eq!(line, params(r#"{"jsonrpc": "2.0", "method": "event",
+                                "params": {"type": "menu_item_click", "id": "open"}}"#));
+
+    client.ok("quit", Value::Null);
+    assert!(!mock.is_running());
+    let event = client.events.recv_timeout(Duration::from_secs(5)).unwrap();
+    let line: Value = serde_json::from_str(&event_notification(&event)).unwrap();
+    assert_eq!(line["params"]["type"], "quit");
+}
+
+#[test]
+fn json_rpc_timers() {
+    let mut client = Client::new();
+    client.hello();
+    client.ok("add_interval", params(r#"{"id": "poll", "ms": 1000}"#));
+    client.ok("add_timeout", params(r#"{"id": "once", "ms": 1500}"#));
+    client.mock.advance(Duration::from_secs(2));
+    let fired: Vec<Value> = client.notifications.try_iter()
+        .map(|line| serde_json::from_str(&line).unwrap())
+        .collect();
+    let ids: Vec<&str> = fired.iter().map(|n| {
+        assert_eq!(n["method"], "timer");
+        n["params"]["id"].as_str().unwrap()
+    }).collect();
+    assert_eq!(ids, vec!["poll", "once", "poll"]);
+
+    client.ok("cancel_timer", params(r#"{"id": "poll"}"#));
+    client.mock.advance(Duration::from_secs(5));
+    assert!(client.notifications.try_recv().is_err());
+    assert_eq!(client.error("cancel_timer", params(r#"{"id": "poll"}"#)), -32602);
+}
+
+#[test]
+fn json_rpc_rejects_bad_requests() {
+    let mut client = Client::new();
+    client.hello();
+    let reply = client.send("{not json").unwrap();
+    assert_eq!(reply["error"]["code"], -32700);
+    assert_eq!(reply["id"], Value::Null);
+    let reply = client.send(r#"{"id": 7, "method": "hide_icon"}"#).unwrap();
+    assert_eq!(reply["error"]["code"], -32600);
+    assert_eq!(client.error("frobnicate", Value::Null), -32601);
+    assert_eq!(client.error("set_tooltip", Value::Null), -32602);
+    assert_eq!(client.error("set_tooltip", params(r#"{"tooltip": 5}"#)), -32602);
+    assert_eq!(client.error("show_icon", params(r#"{"file": "a", "resource": "b"}"#)), -32602);
+    assert_eq!(client.error("set_menu_item_enabled", params(r#"{"id": "nope", "enabled": true}"#)), -32602);
+    client.ok("add_menu_item", params(r#"{"id": "a", "label": "A"}"#));
+    assert_eq!(client.error("add_menu_item", params(r#"{"id": "a", "label": "B"}"#)), -32602);
+
+    client.mock.set_capabilities(Default::default());
+    assert_eq!(client.error("notify", params(r#"{"title": "t", "body": "b"}"#)), -32000);
+
+    // Notifications get no reply, failed or not.
+    assert!(client.send(r#"{"jsonrpc": "2.0", "method": "hide_icon"}"#).is_none());
+    assert!(client.send(r#"{"jsonrpc": "2.0", "method": "frobnicate"}"#).is_none());
+}
+
+#[test]
+fn json_rpc_answers_invalid_requests_with_a_null_id() {
+    let mut client = Client::new();
+    client.hello();
+    for line in &["[]", r#"[{"jsonrpc": "2.0", "id": 1, "method": "hide_icon"}]"#, "42", r#""x""#,
+                  r#"{"id": 3}"#, r#"{"jsonrpc": "2.0", "id": {}, "method": "hide_icon"}"#,
+                  r#"{"method": "hide_icon"}"#] {
+        let reply = client.send(line).expect(line);
+        assert_eq!(reply["jsonrpc"], "2.0");
+        assert_eq!(reply["error"]["code"], -32600, "{}", line);
+        assert_eq!(reply["id"], Value::Null, "{}", line);
+    }
+    // Valid ones still get theirs.
+    let reply = client.send(r#"{"jsonrpc": "2.0", "id": "a", "method": "hide_icon"}"#).unwrap();
+    assert_eq!(reply["id"], "a");
+    assert_eq!(reply["result"], Value::Null);
+}
@@ -0,0 +1,187 @@
+// Runs the GTK/AppIndicator backend for real, so these need an X server.
+// CI provides one through xvfb-run, without one they are skipped. Every tray
+// in the process lives on the backend's one gtk thread, so the tests are free
+// to run in parallel.
+#![cfg(target_os = "linux")]
+
+extern crate systray;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+use systray::{Application, IconResource, MenuItemKind, SystrayError, SystrayEvent};
+
+fn have_display() -> bool {
+    if env::var_os("DISPLAY").is_none() {
+        println!("DISPLAY not set, skipping");
+        return false;
+    }
+    true
+}
+
+#[test]
+fn linux_full_contract() {
+    if !have_display() {
+        return;
+    }
+    let (tx, rx) = channel();
+    let mut app = Application::new(tx).unwrap();
+
+    let caps = app.capabilities();
+    assert!(caps.tooltip);
+    assert!(caps.hide_show);
+    assert!(caps.label);
+    assert!(!caps.left_click);
+    assert!(!caps.notifications);
+    assert!(caps.submenus);
+    assert!(caps.check_items);
+
+    app.add_menu_item(&"First".to_string(), |_| {}).unwrap();
+    app.add_menu_separator().unwrap();
+    let second = app.add_menu_item(&"Second".to_string(), |_| {}).unwrap();
+    app.set_menu_item_label(second, &"Second!".to_string()).unwrap();
+    app.set_menu_item_enabled(second, false).unwrap();
+    app.set_menu_item_label(second, &"Second".to_string()).unwrap();
+    let more = app.add_submenu(None, &"More".to_string()).unwrap();
+    let check = app.add_menu_entry(Some(more), &"Check".to_string(), MenuItemKind::Check(true), |_| {}).unwrap();
+    app.add_menu_separator_in(Some(more)).unwrap();
+    app.add_menu_entry(Some(more), &"One".to_string(), MenuItemKind::Radio(true), |_| {}).unwrap();
+    let two = app.add_menu_entry(Some(more), &"Two".to_string(), MenuItemKind::Radio(false), |_| {}).unwrap();
+    app.set_menu_item_checked(check, false).unwrap();
+    app.set_menu_item_checked(two, true).unwrap();
+
+    app.set_tooltip("systray-rs test".to_string()).unwrap();
+    app.show_icon(IconResource::Resource("dialog-information".to_string())).unwrap();
+    app.set_label(Some("42%".to_string()), Some("100%".to_string())).unwrap();
+    app.set_label(None, None).unwrap();
+    app.hide_icon().unwrap();
+    app.show_icon(IconResource::File("./rust.ico".to_string())).unwrap();
+
+    app.quit();
+    app.quit();
+    match rx.recv_timeout(Duration::from_secs(5)) {
+        Ok(SystrayEvent::Quit) => {},
+        _ => panic!("expected a Quit event"),
+    }
+    assert!(rx.recv().is_err());
+}
+
+#[test]
+fn linux_reports_missing_icon_file() {
+    if !have_display() {
+        return;
+    }
+    let (tx, _rx) = channel();
+    let mut app = Application::new(tx).unwrap();
+    match app.show_icon(IconResource::File("/nonexistent/icon.png".to_string())) {
+        Err(SystrayError::OsError(_)) => {},
+        _ => panic!("missing icon file was accepted"),
+    }
+}
+
+#[test]
+fn linux_calls_after_quit_fail() {
+    if !have_display() {
+        return;
+    }
+    let (tx, _rx) = channel();
+    let mut app = Application::new(tx).unwrap();
+    let handle = app.handle();
+    app.quit();
+    match handle.set_tooltip("too late".to_string()).wait() {
+        Ok(()) => {},
+        Err(e) => panic!("tooltip on a hidden icon only updates state: {}", e),
+    }
+    match handle.add_menu_item("too late".to_string()).wait() {
+        Err(SystrayError::BackendGone) => {},
+        _ => panic!("expected BackendGone"),
+    }
+}
+
+#[test]
+fn linux_trays_side_by_side() {
+    if !have_display() {
+        return;
+    }
+    let (tx1, rx1) = channel();
+    let (tx2, rx2) = channel();
+    let mut first = Application::new(tx1).unwrap();
+    let mut second = Application::new(tx2).unwrap();
+    first.add_menu_item(&"First".to_string(), |_| {}).unwrap();
+    second.add_menu_item(&"Second".to_string(), |_| {}).unwrap();
+    first.show_icon(IconResource::Resource("dialog-information".to_string())).unwrap();
+    second.show_icon(IconResource::Resource("dialog-warning".to_string())).unwrap();
+
+    // Quitting one leaves the other running.
+    first.quit();
+    match rx1.recv_timeout(Duration::from_secs(5)) {
+        Ok(SystrayEvent::Quit) => {},
+        _ => panic!("expected a Quit event"),
+    }
+    second.set_tooltip("still here".to_string()).unwrap();
+    assert!(rx2.try_recv().is_err());
+}
+
+#[test]
+fn linux_tray_after_the_last_one_quit() {
+    if !have_display() {
+        return;
+    }
+    // Quitting the last tray stops the gtk loop, the next one starts it again.
+    for _ in 0..2 {
+        let (tx, rx) = channel();
+        let mut app = Application::new(tx).unwrap();
+        app.show_icon(IconResource::Resource("dialog-information".to_string())).unwrap();
+        app.quit();
+        match rx.recv_timeout(Duration::from_secs(5)) {
+            Ok(SystrayEvent::Quit) => {},
+            _ => panic!("expected a Quit event"),
+        }
+    }
+}
+
+#[test]
+fn linux_shows_an_unchanged_icon_file_anew() {
+    if !have_display() {
+        return;
+    }
+    let (tx, _rx) = channel();
+    let mut app = Application::new(tx).unwrap();
+    app.show_icon(IconResource::File("./rust.ico".to_string())).unwrap();
+    let first = app.backend_icon_name().unwrap().unwrap();
+    assert_eq!(first, "./rust.ico");
+
+    // The indicator ignores a name it already has, hosts cache by path.
+    app.hide_icon().unwrap();
+    app.show_icon(IconResource::File("./rust.ico".to_string())).unwrap();
+    let second = app.backend_icon_name().unwrap().unwrap();
+    assert!(second != first, "icon kept its name {}", first);
+    assert_eq!(fs::canonicalize(&second).unwrap(), fs::canonicalize(Path::new(&first)).unwrap());
+}
+
+#[cfg(feature = "hot-reload")]
+#[test]
+fn linux_hot_reload_shows_the_changed_icon() {
+    if !have_display() {
+        return;
+    }
+    let dir = env::temp_dir().join(format!("systray-linux-reload-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let icon = dir.join("icon.ico");
+    fs::copy("./rust.ico", &icon).unwrap();
+    let (tx, rx) = channel();
+    let mut app = Application::new(tx).unwrap();
+    app.show_icon(IconResource::File(icon.display().to_string())).unwrap();
+    app.enable_hot_reload().unwrap();
+    let before = app.backend_icon_name().unwrap();
+
+    fs::copy("./rust.ico", &icon).unwrap();
+    let event = rx.recv_timeout(Duration::from_secs(5));
+    let after = app.backend_icon_name().unwrap();
+    app.quit();
+    fs::remove_dir_all(&dir).ok();
+    assert_eq!(event.unwrap(), SystrayEvent::Reloaded(icon.display().to_string()));
+    assert!(after != before, "icon kept its name {:?}", before);
+}
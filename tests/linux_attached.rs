@@ -0,0 +1,60 @@
+// The attached backend shares gtk with the test itself, which can't be mixed
+// with the threaded one in tests/linux.rs inside one process.
+#![cfg(target_os = "linux")]
+
+extern crate gtk;
+extern crate systray;
+
+use std::env;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+use systray::{Application, Backend, IconResource, SystrayEvent};
+
+fn pump_gtk() {
+    while gtk::events_pending() {
+        gtk::main_iteration();
+    }
+}
+
+#[test]
+fn linux_attached_to_main_context() {
+    if env::var_os("DISPLAY").is_none() {
+        println!("DISPLAY not set, skipping");
+        return;
+    }
+    gtk::init().unwrap();
+    let (tx, rx) = channel();
+    let mut app = Application::builder()
+        .backend(Backend::GlibMainContext)
+        .event_sink(tx)
+        .build()
+        .unwrap();
+    // On the loop's own thread everything runs right away.
+    app.add_menu_item(&"First".to_string(), |_| {}).unwrap();
+    app.show_icon(IconResource::Resource("dialog-information".to_string())).unwrap();
+
+    // Other threads wait for the loop to run their calls.
+    let handle = app.handle();
+    let (done_tx, done) = channel();
+    thread::spawn(move || {
+        done_tx.send(handle.set_tooltip("from another thread".to_string()).wait()).unwrap();
+    });
+    thread::sleep(Duration::from_millis(100));
+    assert!(done.try_recv().is_err());
+    let reply = loop {
+        pump_gtk();
+        if let Ok(reply) = done.recv_timeout(Duration::from_millis(10)) {
+            break reply;
+        }
+    };
+    reply.unwrap();
+
+    app.quit();
+    pump_gtk();
+    match rx.recv_timeout(Duration::from_secs(5)) {
+        Ok(SystrayEvent::Quit) => {},
+        _ => panic!("expected a Quit event"),
+    }
+    assert!(rx.recv().is_err());
+}
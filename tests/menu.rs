@@ -0,0 +1,176 @@
+#[macro_use]
+extern crate systray;
+
+use std::sync::mpsc::channel;
+use std::time::Duration;
+use systray::{Backend, Builder, Capabilities, Menu, MenuEntryState, MenuItemKind, MockMenuEntry, MockTray,
+              SystrayError, SystrayEvent};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Action {
+    Open,
+    Weekly,
+    Pause,
+    Daily,
+}
+
+#[test]
+fn menu_macro_builds_menus() {
+    let mock = MockTray::new();
+    let (tx, rx) = channel();
+    let weekly = "Weekly".to_string();
+    let mut app = Builder::new()
+        .menu(menu! {
+            "Open" => payload(Action::Open),
+            separator,
+            weekly => payload(Action::Weekly),
+            separator,
+            "Quit" => |app| app.quit(),
+        })
+        .backend(Backend::Mock(mock.clone()))
+        .event_sink(tx)
+        .build()
+        .unwrap();
+    assert_eq!(mock.menu(), vec![MockMenuEntry::Item(0, "Open".to_string()),
+                                 MockMenuEntry::Separator(1),
+                                 MockMenuEntry::Item(2, "Weekly".to_string()),
+                                 MockMenuEntry::Separator(3),
+                                 MockMenuEntry::Item(4, "Quit".to_string())]);
+
+    assert!(mock.click_menu_item(2));
+    match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+        SystrayEvent::MenuItemClick(action) => assert_eq!(action, Action::Weekly),
+        event => panic!("expected a click, got {:?}", event),
+    }
+
+    // Callbacks run on this thread once asked to, clicks are handled in
+    // order so Quit's is queued by the time Open's event shows up.
+    assert!(mock.click_menu_item(4));
+    assert!(mock.click_menu_item(0));
+    match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+        SystrayEvent::MenuItemClick(action) => assert_eq!(action, Action::Open),
+        event => panic!("expected a click, got {:?}", event),
+    }
+    assert!(mock.is_running());
+    app.run_callbacks();
+    assert!(!mock.is_running());
+}
+
+#[test]
+fn menu_macro_takes_empty_and_trailing_commas() {
+    let empty: Menu<u32> = menu! {};
+    let mock = MockTray::new();
+    let _app = Builder::new()
+        .menu(empty)
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    assert_eq!(mock.menu(), vec![]);
+
+    let mock = MockTray::new();
+    let _app = Builder::<u32>::new()
+        .menu(menu! { separator, "Mute" => |_| {}, })
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    assert_eq!(mock.menu(), vec![MockMenuEntry::Separator(0),
+                                 MockMenuEntry::Item(1, "Mute".to_string())]);
+}
+
+fn checkable_mock() -> MockTray {
+    let mock = MockTray::new();
+    mock.set_capabilities(Capabilities { submenus: true, check_items: true, ..Capabilities::default() });
+    mock
+}
+
+#[test]
+fn menu_macro_builds_check_radio_and_submenus() {
+    let mock = checkable_mock();
+    let (tx, rx) = channel();
+    let app = Builder::new()
+        .menu(menu! {
+            "Paused" => check(false, payload(Action::Pause)),
+            "Schedule" => submenu {
+                "Daily" => radio(true, payload(Action::Daily)),
+                "Weekly" => radio(false, payload(Action::Weekly)),
+                separator,
+                "Never" => radio(false, |_| {}),
+            },
+        })
+        .backend(Backend::Mock(mock.clone()))
+        .event_sink(tx)
+        .build()
+        .unwrap();
+    assert_eq!(mock.menu(), vec![MockMenuEntry::Check(0, "Paused".to_string(), false),
+                                 MockMenuEntry::Submenu(1, "Schedule".to_string(), vec![
+                                     MockMenuEntry::Radio(2, "Daily".to_string(), true),
+                                     MockMenuEntry::Radio(3, "Weekly".to_string(), false),
+                                     MockMenuEntry::Separator(4),
+                                     MockMenuEntry::Radio(5, "Never".to_string(), false)])]);
+
+    // The marks have moved by the time the click shows up.
+    assert!(mock.click_menu_item(3));
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), SystrayEvent::MenuItemClick(Action::Weekly));
+    assert_eq!(app.state().menu[1], MenuEntryState::Submenu(1, "Schedule".to_string(), true, vec![
+        MenuEntryState::Radio(2, "Daily".to_string(), true, false),
+        MenuEntryState::Radio(3, "Weekly".to_string(), true, true),
+        MenuEntryState::Separator(4),
+        MenuEntryState::Radio(5, "Never".to_string(), true, false)]));
+    assert!(mock.click_menu_item(0));
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), SystrayEvent::MenuItemClick(Action::Pause));
+    assert_eq!(app.state().menu[0], MenuEntryState::Check(0, "Paused".to_string(), true, true));
+
+    // Submenus only open.
+    assert!(!mock.click_menu_item(1));
+}
+
+#[test]
+fn marks_are_set_where_the_backend_has_them() {
+    let mock = checkable_mock();
+    let mut app = Builder::<u32>::new().backend(Backend::Mock(mock.clone())).build().unwrap();
+    let more = app.add_submenu(None, &"More".to_string()).unwrap();
+    let a = app.add_menu_entry_with(Some(more), &"A".to_string(), MenuItemKind::Radio(true), 1).unwrap();
+    let b = app.add_menu_entry_with(Some(more), &"B".to_string(), MenuItemKind::Radio(false), 2).unwrap();
+    app.set_menu_item_checked(b, true).unwrap();
+    assert_eq!(mock.menu(), vec![MockMenuEntry::Submenu(more, "More".to_string(), vec![
+                                     MockMenuEntry::Radio(a, "A".to_string(), false),
+                                     MockMenuEntry::Radio(b, "B".to_string(), true)])]);
+    match app.set_menu_item_checked(more, true) {
+        Err(SystrayError::WrongMenuItemKind(idx)) => assert_eq!(idx, more),
+        r => panic!("expected WrongMenuItemKind, got {:?}", r),
+    }
+    match app.add_menu_entry_with(Some(a), &"C".to_string(), MenuItemKind::Plain, 3) {
+        Err(SystrayError::WrongMenuItemKind(idx)) => assert_eq!(idx, a),
+        r => panic!("expected WrongMenuItemKind, got {:?}", r),
+    }
+
+    let mock = MockTray::new();
+    let mut app = Builder::<u32>::new().backend(Backend::Mock(mock.clone())).build().unwrap();
+    match app.add_submenu(None, &"More".to_string()) {
+        Err(SystrayError::NotImplementedError) => {}
+        r => panic!("expected NotImplementedError, got {:?}", r),
+    }
+    match app.add_menu_entry_with(None, &"Paused".to_string(), MenuItemKind::Check(false), 1) {
+        Err(SystrayError::NotImplementedError) => {}
+        r => panic!("expected NotImplementedError, got {:?}", r),
+    }
+    assert_eq!(mock.menu(), vec![]);
+    assert_eq!(app.state().menu, vec![]);
+}
+
+#[test]
+fn menu_items_can_start_out_disabled() {
+    let mock = checkable_mock();
+    let app = Builder::new()
+        .menu(Menu::new()
+              .item_with("Open", Action::Open).enabled(false)
+              .separator().enabled(false)
+              .submenu("Schedule", Menu::new().radio_item_with("Daily", true, Action::Daily)).enabled(false))
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    assert!(!mock.is_enabled(0));
+    assert!(!mock.is_enabled(2));
+    assert!(mock.is_enabled(3));
+    assert_eq!(app.state().menu[0], MenuEntryState::Item(0, "Open".to_string(), false));
+}
@@ -0,0 +1,338 @@
+extern crate systray;
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+use systray::{Application, Backend, Builder, Capabilities, IconResource, IndicatorCategory, Menu,
+              MockMenuEntry, MockTray, SystrayError, SystrayEvent};
+
+fn timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+#[test]
+fn builder_hands_over_complete_tray() {
+    let mock = MockTray::new();
+    let _app = Application::builder()
+        .id("org.example.Test")
+        .title("Test")
+        .category(IndicatorCategory::Communications)
+        .tooltip("Testing")
+        .icon(IconResource::Resource("mail-unread".to_string()))
+        .menu(Menu::new()
+              .item("Open", |_| {})
+              .separator()
+              .item("Quit", |_| {}))
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+
+    assert_eq!(mock.id(), "org.example.Test");
+    assert_eq!(mock.title(), "Test");
+    assert_eq!(mock.category(), IndicatorCategory::Communications);
+    assert_eq!(mock.tooltip(), "Testing");
+    assert!(mock.is_visible());
+    assert_eq!(mock.icon(), Some(IconResource::Resource("mail-unread".to_string())));
+    assert_eq!(mock.menu(), vec![MockMenuEntry::Item(0, "Open".to_string()),
+                                 MockMenuEntry::Separator(1),
+                                 MockMenuEntry::Item(2, "Quit".to_string())]);
+}
+
+#[test]
+fn host_restart_replays_state() {
+    let mock = MockTray::new();
+    let (tx, rx) = channel();
+    let mut app = Application::builder()
+        .backend(Backend::Mock(mock.clone()))
+        .event_sink(tx)
+        .build()
+        .unwrap();
+    let item = app.add_menu_item(&"Item".to_string(), |_| {}).unwrap();
+    app.set_menu_item_enabled(item, false).unwrap();
+    app.set_tooltip("tip".to_string()).unwrap();
+    app.show_icon(IconResource::File("icon.png".to_string())).unwrap();
+    app.set_label(Some("42%".to_string()), Some("100%".to_string())).unwrap();
+
+    assert!(mock.restart_host());
+    match rx.recv_timeout(timeout()) {
+        Ok(SystrayEvent::HostChanged) => {},
+        _ => panic!("expected HostChanged"),
+    }
+    assert!(mock.is_visible());
+    assert_eq!(mock.tooltip(), "tip");
+    assert_eq!(mock.label(), "42%");
+    assert_eq!(mock.menu(), vec![MockMenuEntry::Item(0, "Item".to_string())]);
+    assert!(!mock.is_enabled(item));
+}
+
+#[test]
+fn quit_is_idempotent_and_ends_events() {
+    let mock = MockTray::new();
+    let (tx, rx) = channel();
+    let mut app = Application::builder()
+        .backend(Backend::Mock(mock.clone()))
+        .event_sink(tx)
+        .build()
+        .unwrap();
+    assert!(mock.click_menu_item(3));
+    app.quit();
+    app.quit();
+    assert!(!mock.is_running());
+    match rx.recv_timeout(timeout()) {
+        Ok(SystrayEvent::MenuItemClick(3)) => {},
+        _ => panic!("expected the click"),
+    }
+    match rx.recv_timeout(timeout()) {
+        Ok(SystrayEvent::Quit) => {},
+        _ => panic!("expected Quit"),
+    }
+    assert!(rx.recv().is_err());
+}
+
+#[test]
+fn label_from_handle() {
+    let mock = MockTray::new();
+    let mut app = Application::builder()
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    let handle = app.handle();
+    for i in 0..100 {
+        let _ = handle.set_label(Some(format!("{}", i)), Some("100".to_string()));
+    }
+    handle.set_label(Some("done".to_string()), None).wait().unwrap();
+    assert_eq!(mock.label(), "done");
+    assert_eq!(mock.label_guide(), "");
+    app.set_label(None, None).unwrap();
+    assert_eq!(mock.label(), "");
+
+    mock.set_capabilities(Capabilities::default());
+    match app.set_label(Some("x".to_string()), None) {
+        Err(SystrayError::NotImplementedError) => {},
+        _ => panic!("label accepted without support"),
+    }
+}
+
+#[test]
+fn handles_work_from_other_threads() {
+    let mock = MockTray::new();
+    let app = Application::builder()
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    let handle = app.handle();
+    let threads: Vec<_> = (0..4).map(|i| {
+        let handle = handle.clone();
+        thread::spawn(move || handle.add_menu_item(format!("Item {}", i)).wait())
+    }).collect();
+    let mut idxs: Vec<u32> = threads.into_iter().map(|t| t.join().unwrap().unwrap()).collect();
+    idxs.sort();
+    assert_eq!(idxs, vec![0, 1, 2, 3]);
+    assert_eq!(mock.menu().len(), 4);
+
+    let tooltip = handle.clone();
+    thread::spawn(move || tooltip.set_tooltip("from a thread".to_string()).wait())
+        .join().unwrap().unwrap();
+    assert_eq!(handle.state().wait().unwrap().tooltip, "from a thread");
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Action {
+    Open,
+    Quit,
+}
+
+#[test]
+fn clicks_carry_typed_payloads() {
+    let mock = MockTray::new();
+    let (tx, rx) = channel();
+    let mut app = Builder::new()
+        .menu(Menu::new()
+              .item_with("Open", Action::Open)
+              .separator())
+        .backend(Backend::Mock(mock.clone()))
+        .event_sink(tx)
+        .build()
+        .unwrap();
+    let quit = app.add_menu_item_with(&"Quit".to_string(), Action::Quit).unwrap();
+    let plain = app.add_menu_item(&"No payload".to_string(), |_| {}).unwrap();
+
+    assert!(mock.click_menu_item(plain));
+    assert!(mock.click_menu_item(quit));
+    assert!(mock.click_menu_item(0));
+    match rx.recv_timeout(timeout()) {
+        Ok(SystrayEvent::MenuItemClick(a)) => assert_eq!(a, Action::Quit),
+        _ => panic!("expected the Quit click"),
+    }
+    match rx.recv_timeout(timeout()) {
+        Ok(SystrayEvent::MenuItemClick(a)) => assert_eq!(a, Action::Open),
+        _ => panic!("expected the Open click"),
+    }
+}
+
+#[test]
+fn callbacks_run_for_typed_applications() {
+    let mock = MockTray::new();
+    let (tx, rx) = channel();
+    let counted = Rc::new(Cell::new(0));
+    let counter = counted.clone();
+    let mut app = Builder::new()
+        .menu(Menu::new()
+              .item_with("Open", Action::Open)
+              .item("Count", move |_| counter.set(counter.get() + 1)))
+        .backend(Backend::Mock(mock.clone()))
+        .event_sink(tx)
+        .build()
+        .unwrap();
+
+    assert!(mock.click_menu_item(1));
+    assert!(mock.click_menu_item(0));
+    // Clicks are handled in order, so the callback is queued by now.
+    match rx.recv_timeout(timeout()) {
+        Ok(SystrayEvent::MenuItemClick(a)) => assert_eq!(a, Action::Open),
+        _ => panic!("expected the Open click"),
+    }
+    assert_eq!(counted.get(), 0);
+    app.run_callbacks();
+    assert_eq!(counted.get(), 1);
+    app.run_callbacks();
+    assert_eq!(counted.get(), 1);
+}
+
+#[test]
+fn closure_sink_gets_events() {
+    let mock = MockTray::new();
+    let (tx, rx) = channel();
+    let _app = Application::builder()
+        .backend(Backend::Mock(mock.clone()))
+        .event_sink(move |event| {
+            if let SystrayEvent::LeftButtonClick = event {
+                tx.send(()).unwrap();
+            }
+        })
+        .build()
+        .unwrap();
+    assert!(mock.left_click());
+    assert!(rx.recv_timeout(timeout()).is_ok());
+}
+
+#[test]
+fn closed_sink_shuts_tray_down() {
+    let mock = MockTray::new();
+    let (tx, rx) = channel();
+    let _app = Application::builder()
+        .backend(Backend::Mock(mock.clone()))
+        .event_sink(tx)
+        .build()
+        .unwrap();
+    drop(rx);
+    assert!(mock.left_click());
+    let start = Instant::now();
+    while mock.is_running() {
+        assert!(start.elapsed() < timeout(), "tray kept running without a listener");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(!mock.is_visible());
+}
+
+#[test]
+fn trays_route_events_separately() {
+    let (mock1, mock2) = (MockTray::new(), MockTray::new());
+    let (tx1, rx1) = channel();
+    let (tx2, rx2) = channel();
+    let _first = Application::builder()
+        .backend(Backend::Mock(mock1.clone()))
+        .menu(Menu::new().item("One", |_| {}))
+        .event_sink(tx1)
+        .build()
+        .unwrap();
+    let _second = Application::builder()
+        .backend(Backend::Mock(mock2.clone()))
+        .menu(Menu::new().item("Two", |_| {}))
+        .event_sink(tx2)
+        .build()
+        .unwrap();
+    assert_eq!(mock1.menu(), vec![MockMenuEntry::Item(0, "One".to_string())]);
+    assert_eq!(mock2.menu(), vec![MockMenuEntry::Item(0, "Two".to_string())]);
+
+    assert!(mock2.left_click());
+    match rx2.recv_timeout(timeout()) {
+        Ok(SystrayEvent::LeftButtonClick) => {},
+        _ => panic!("expected the click on the second tray"),
+    }
+    assert!(rx1.try_recv().is_err());
+}
+
+#[test]
+fn timers_follow_the_virtual_clock() {
+    let mock = MockTray::new();
+    let mut app = Application::builder()
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    let (tx, rx) = channel();
+    let once_tx = tx.clone();
+    app.add_timeout(Duration::from_secs(5), move |_| {
+        once_tx.send("timeout").unwrap();
+    }).unwrap();
+    let mut ticks = 0;
+    let every = app.add_interval(Duration::from_secs(2), move |tray| {
+        ticks += 1;
+        tray.set_label(Some(format!("{}", ticks)), None);
+        tx.send("interval").unwrap();
+    }).unwrap();
+
+    mock.advance(Duration::from_secs(1));
+    assert!(rx.try_recv().is_err());
+    mock.advance(Duration::from_secs(5));
+    let fired: Vec<_> = rx.try_iter().collect();
+    assert_eq!(fired, vec!["interval", "interval", "timeout", "interval"]);
+    assert_eq!(mock.timer_count(), 1);
+
+    every.cancel().wait().unwrap();
+    assert_eq!(mock.timer_count(), 0);
+    mock.advance(Duration::from_secs(10));
+    assert!(rx.try_recv().is_err());
+    assert_eq!(mock.label(), "3");
+}
+
+#[test]
+fn timers_change_the_tray_without_waiting() {
+    let mock = MockTray::new();
+    let mut app = Application::builder()
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    let (tx, rx) = channel();
+    let handle = app.handle();
+    app.add_timeout(Duration::from_secs(1), move |tray| {
+        tray.set_tooltip("from a timer".to_string());
+        // A handle taken along still can't wait there.
+        tx.send(handle.set_label(Some("1".to_string()), None).wait()).unwrap();
+    }).unwrap();
+    mock.advance(Duration::from_secs(1));
+    match rx.try_recv() {
+        Ok(Err(SystrayError::WaitInTimer)) => {},
+        _ => panic!("expected WaitInTimer"),
+    }
+    let state = app.handle().state().wait().unwrap();
+    assert_eq!(state.tooltip, "from a timer");
+    assert_eq!(state.label, "1");
+}
+
+#[test]
+fn overlong_timer_intervals_are_rejected() {
+    let mock = MockTray::new();
+    let mut app = Application::builder()
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    // 50 days is past what fits in u32 milliseconds.
+    match app.add_timeout(Duration::from_secs(50 * 24 * 3600), |_| {}) {
+        Err(SystrayError::IntervalTooLong) => {},
+        _ => panic!("expected IntervalTooLong"),
+    }
+    assert_eq!(mock.timer_count(), 0);
+}
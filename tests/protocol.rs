@@ -0,0 +1,139 @@
+extern crate systray;
+
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+use systray::{Backend, Builder, IconResource, Menu, MockMenuEntry, MockTray, StateBundle, SystrayError,
+              SystrayEvent, TrayStates};
+use systray::protocol::{Command, CommandError, event_line, error_line, error_message};
+
+fn parse(line: &str) -> Command {
+    Command::parse(line).unwrap()
+}
+
+fn parse_error(line: &str) -> String {
+    match Command::parse(line) {
+        Err(CommandError::BadCommand(msg)) => msg,
+        other => panic!("{:?} parsed to {:?}", line, other),
+    }
+}
+
+#[test]
+fn protocol_parses_commands() {
+    assert_eq!(parse("icon /tmp/some icon.png"), Command::Icon("/tmp/some icon.png".to_string()));
+    assert_eq!(parse("  hide  "), Command::Hide);
+    assert_eq!(parse("tooltip Backing up, 3 files left"),
+               Command::Tooltip("Backing up, 3 files left".to_string()));
+    assert_eq!(parse("tooltip"), Command::Tooltip("".to_string()));
+    assert_eq!(parse("label \"  42% \""), Command::Label("  42% ".to_string()));
+    assert_eq!(parse("add-item run Run now"),
+               Command::AddItem("run".to_string(), "Run now".to_string()));
+    assert_eq!(parse("add-item \"run \\\"it\\\"\" Run"),
+               Command::AddItem("run \"it\"".to_string(), "Run".to_string()));
+    assert_eq!(parse("separator"), Command::Separator);
+    assert_eq!(parse("set-enabled run false"), Command::SetEnabled("run".to_string(), false));
+    assert_eq!(parse("set-label run Run again"), Command::SetItemLabel("run".to_string(), "Run again".to_string()));
+    assert_eq!(parse("notify \"Backup done\" 3 files in 2s"),
+               Command::Notify("Backup done".to_string(), "3 files in 2s".to_string()));
+    assert_eq!(parse("state"), Command::State);
+    assert_eq!(parse("set-state  offline "), Command::SetState("offline".to_string()));
+    assert_eq!(parse("quit"), Command::Quit);
+    let lines = ["icon \"/tmp/some icon.png\"", "tooltip \"\"", "add-item \"run \\\"it\\\"\" Run",
+                 "set-enabled run false", "notify Mail \"You have mail\"", "state",
+                 "set-state \"sync failed\"", "set-label run \"Run again\""];
+    for line in lines.iter() {
+        assert_eq!(&parse(line).to_string(), line);
+    }
+
+    assert!(parse_error("frobnicate now").contains("unknown command frobnicate"));
+    assert!(parse_error("quit now").contains("no arguments"));
+    assert!(parse_error("state busy").contains("no arguments"));
+    assert!(parse_error("set-state").contains("missing"));
+    assert!(parse_error("set-enabled run maybe").contains("true or false"));
+    assert!(parse_error("add-item run").contains("missing"));
+    assert!(parse_error("notify \"unfinished").contains("unterminated"));
+    assert!(parse_error("").contains("empty"));
+}
+
+#[test]
+fn protocol_drives_tray() {
+    let mock = MockTray::new();
+    let (tx, rx) = channel();
+    let mut app = Builder::new()
+        .menu(Menu::new().item_with("From spec", "spec".to_string()))
+        .backend(Backend::Mock(mock.clone()))
+        .event_sink(tx)
+        .build()
+        .unwrap();
+    let lines = ["icon mail-unread", "tooltip Two new", "label 2", "separator",
+                 "add-item open Open inbox", "set-enabled spec false",
+                 "set-label spec \"From the spec\"", "notify Mail \"You have mail\""];
+    for line in lines.iter() {
+        assert_eq!(parse(line).run(&mut app).unwrap(), Vec::<String>::new());
+    }
+    assert_eq!(mock.icon(), Some(IconResource::Resource("mail-unread".to_string())));
+    assert_eq!(mock.tooltip(), "Two new");
+    assert_eq!(mock.label(), "2");
+    assert_eq!(mock.menu(), vec![MockMenuEntry::Item(0, "From the spec".to_string()),
+                                 MockMenuEntry::Separator(1),
+                                 MockMenuEntry::Item(2, "Open inbox".to_string())]);
+    assert!(!mock.is_enabled(0));
+    assert!(mock.is_enabled(2));
+    assert_eq!(mock.notifications(), vec![("Mail".to_string(), "You have mail".to_string())]);
+
+    match parse("add-item open Again").run(&mut app) {
+        Err(e @ CommandError::DuplicateId(_)) => {
+            assert_eq!(error_line(&e), "error \"menu item id open is taken\"");
+            assert_eq!(error_message(&error_line(&e)), Some(e.to_string()));
+        }
+        _ => panic!("id used twice"),
+    }
+    match parse("set-enabled nope true").run(&mut app) {
+        Err(CommandError::UnknownId(_)) => {},
+        _ => panic!("unknown id accepted"),
+    }
+    parse("hide").run(&mut app).unwrap();
+    assert!(!mock.is_visible());
+    assert_eq!(parse("state").run(&mut app).unwrap(),
+               vec!["state icon resource mail-unread", "state visible false",
+                    "state tooltip \"Two new\"", "state label 2",
+                    "state item spec false \"From the spec\"", "state separator",
+                    "state item open true \"Open inbox\""]);
+
+    app.set_states(TrayStates::new().state("busy", StateBundle::new().tooltip("Busy")));
+    assert_eq!(parse("set-state busy").run(&mut app).unwrap(), Vec::<String>::new());
+    // The icon is hidden, so only the tray's own state has the new tooltip.
+    let lines = parse("state").run(&mut app).unwrap();
+    assert!(lines.contains(&"state tooltip Busy".to_string()));
+    assert!(lines.contains(&"state current busy".to_string()));
+    match parse("set-state idle").run(&mut app) {
+        Err(CommandError::Tray(SystrayError::UnknownState(name))) => assert_eq!(name, "idle"),
+        _ => panic!("unknown state accepted"),
+    }
+
+    assert!(mock.click_menu_item(2));
+    let event = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(event_line(&event), "click open");
+    parse("quit").run(&mut app).unwrap();
+    assert_eq!(event_line(&rx.recv_timeout(Duration::from_secs(5)).unwrap()), "quit");
+    assert_eq!(event_line(&SystrayEvent::MenuItemClick("a b".to_string())), "click \"a b\"");
+    assert_eq!(event_line(&SystrayEvent::ReloadFailed("tray.toml:2: bad \"id\"".to_string())),
+               "reload-failed \"tray.toml:2: bad \\\"id\\\"\"");
+}
+
+#[test]
+fn protocol_gives_an_id_to_one_item() {
+    let mock = MockTray::new();
+    let mut app = Builder::new().backend(Backend::Mock(mock.clone())).build().unwrap();
+    let threads: Vec<_> = (0..8).map(|i| {
+        let runner = app.command_runner();
+        thread::spawn(move || runner.run(Command::AddItem("open".to_string(), format!("Open {}", i))))
+    }).collect();
+    let added = threads.into_iter()
+        .map(|thread| thread.join().unwrap())
+        .filter(|result| result.is_ok())
+        .count();
+    assert_eq!(added, 1);
+    assert_eq!(mock.menu().len(), 1);
+    app.quit();
+}
@@ -0,0 +1,65 @@
+#![cfg(all(target_os = "linux", feature = "hot-reload", feature = "config"))]
+
+extern crate systray;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+use systray::{Application, Backend, Builder, IconResource, MockMenuEntry, MockTray, SystrayEvent};
+
+fn write(path: &Path, text: &str) {
+    File::create(path).unwrap().write_all(text.as_bytes()).unwrap();
+}
+
+fn next_event(rx: &Receiver<SystrayEvent<String>>) -> SystrayEvent<String> {
+    rx.recv_timeout(Duration::from_secs(5)).expect("no event")
+}
+
+struct TempDir(PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+#[test]
+fn hot_reload_follows_spec_and_icon() {
+    let dir = TempDir(env::temp_dir().join(format!("systray-reload-{}", std::process::id())));
+    fs::create_dir_all(&dir.0).unwrap();
+    let icon = dir.0.join("icon.png");
+    let spec = dir.0.join("tray.toml");
+    write(&icon, "not really a png");
+    let text = format!("icon = \"{}\"\n\n[[menu]]\nid = \"run\"\nlabel = \"Run now\"\n", icon.display());
+    write(&spec, &text);
+
+    let mock = MockTray::new();
+    let (tx, rx) = channel();
+    let builder = Builder::new().backend(Backend::Mock(mock.clone())).event_sink(tx);
+    let mut app = Application::from_config_with(&spec, builder).unwrap();
+    app.enable_hot_reload().unwrap();
+    app.enable_hot_reload().unwrap();
+    assert_eq!(mock.icon(), Some(IconResource::File(icon.display().to_string())));
+
+    write(&spec, &text.replace("Run now", "Run again"));
+    assert_eq!(next_event(&rx), SystrayEvent::Reloaded(spec.display().to_string()));
+    assert_eq!(mock.menu(), vec![MockMenuEntry::Item(0, "Run again".to_string())]);
+
+    // A broken spec leaves the tray alone.
+    write(&spec, "[[menu]]\nlabel = \"No id\"\n");
+    match next_event(&rx) {
+        SystrayEvent::ReloadFailed(msg) => assert!(msg.contains("has no id"), "{}", msg),
+        event => panic!("expected a failed reload, got {:?}", event),
+    }
+    assert_eq!(mock.menu(), vec![MockMenuEntry::Item(0, "Run again".to_string())]);
+
+    // Saved by renaming a new file over the old one.
+    let new_icon = dir.0.join("icon.png.new");
+    write(&new_icon, "a new icon");
+    fs::rename(&new_icon, &icon).unwrap();
+    assert_eq!(next_event(&rx), SystrayEvent::Reloaded(icon.display().to_string()));
+    app.quit();
+}
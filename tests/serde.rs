@@ -0,0 +1,77 @@
+#![cfg(feature = "serde")]
+
+extern crate serde_json;
+extern crate systray;
+
+use serde_json::Value;
+use systray::{Capabilities, IconResource, IconStatus, Menu, MenuEntryState, SystrayEvent, TrayIcon,
+              TrayState};
+use systray::protocol::Command;
+
+fn json(s: &str) -> Value {
+    serde_json::from_str(s).unwrap()
+}
+
+#[test]
+fn serde_representation() {
+    let events: Vec<SystrayEvent<String>> = vec![SystrayEvent::MenuItemClick("open".to_string()),
+                                                  SystrayEvent::LeftButtonClick,
+                                                  SystrayEvent::HostChanged,
+                                                  SystrayEvent::Quit];
+    assert_eq!(serde_json::to_value(&events).unwrap(),
+               json(r#"[{"menu_item_click": "open"}, "left_button_click", "host_changed", "quit"]"#));
+    let back: Vec<SystrayEvent<String>> = serde_json::from_value(serde_json::to_value(&events).unwrap()).unwrap();
+    assert_eq!(back, events);
+
+    let icon = TrayIcon {
+        status: IconStatus::HIDE,
+        resource: IconResource::File("/tmp/backup.png".to_string()),
+    };
+    assert_eq!(serde_json::to_value(&icon).unwrap(),
+               json(r#"{"status": "hide", "resource": {"file": "/tmp/backup.png"}}"#));
+
+    let state = TrayState {
+        icon: Some(IconResource::Resource("network-idle".to_string())),
+        visible: true,
+        tooltip: "Backup".to_string(),
+        label: "".to_string(),
+        label_guide: "".to_string(),
+        menu: vec![MenuEntryState::Item(0, "Run now".to_string(), false), MenuEntryState::Separator(1)],
+        current_state: Some("idle".to_string()),
+    };
+    let value = serde_json::to_value(&state).unwrap();
+    assert_eq!(value["icon"], json(r#"{"resource": "network-idle"}"#));
+    assert_eq!(value["menu"], json(r#"[{"item": [0, "Run now", false]}, {"separator": 1}]"#));
+    assert_eq!(serde_json::from_value::<TrayState>(value).unwrap(), state);
+
+    // Fields from later versions may be missing.
+    let caps: Capabilities = serde_json::from_str(r#"{"tooltip": true}"#).unwrap();
+    assert_eq!(caps, Capabilities { tooltip: true, ..Capabilities::default() });
+
+    let command = Command::AddItem("run".to_string(), "Run now".to_string());
+    assert_eq!(serde_json::to_value(&command).unwrap(), json(r#"{"add_item": ["run", "Run now"]}"#));
+    assert_eq!(serde_json::to_value(&Command::Quit).unwrap(), json(r#""quit""#));
+}
+
+#[test]
+fn serde_menus() {
+    let menu = Menu::new().item_with("Run now", "run".to_string()).separator();
+    let value = serde_json::to_value(&menu).unwrap();
+    assert_eq!(value, json(r#"[{"item": {"label": "Run now", "payload": "run"}}, "separator"]"#));
+    let back: Menu<String> = serde_json::from_value(value.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&back).unwrap(), value);
+
+    let menu = Menu::new()
+        .check_item_with("Paused", true, "pause".to_string())
+        .submenu("Schedule", Menu::new().radio_item_with("Daily", false, "daily".to_string()));
+    let value = serde_json::to_value(&menu).unwrap();
+    assert_eq!(value, json(r#"[{"check": {"label": "Paused", "checked": true, "payload": "pause"}},
+                               {"submenu": {"label": "Schedule", "entries": [
+                                   {"radio": {"label": "Daily", "checked": false, "payload": "daily"}}]}}]"#));
+    let back: Menu<String> = serde_json::from_value(value.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&back).unwrap(), value);
+
+    let menu = Menu::<String>::new().item("Quit", |app| app.quit());
+    let err = serde_json::to_value(&menu).unwrap_err();
+    assert!(err.to_string().contains("\"Quit\" has a callback"));
+}
@@ -0,0 +1,133 @@
+extern crate systray;
+
+use systray::{Application, Backend, Capabilities, IconResource, Menu, MockMenuEntry, MockTray, StateBundle,
+              SystrayError, TrayStates};
+
+fn resource(name: &str) -> Option<IconResource> {
+    Some(IconResource::Resource(name.to_string()))
+}
+
+#[test]
+fn states_apply_whole_bundles() {
+    let mock = MockTray::new();
+    let mut app = Application::builder()
+        .icon(IconResource::Resource("network-idle".to_string()))
+        .tooltip("Up to date")
+        .menu(Menu::new().item_with("Sync now", 0).item_with("Pause", 1))
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    app.set_states(TrayStates::new()
+        .state("idle", StateBundle::new()
+            .icon(IconResource::Resource("network-idle".to_string()))
+            .tooltip("Up to date")
+            .label(1, "Pause")
+            .enabled(0, true))
+        .state("syncing", StateBundle::new()
+            .icon(IconResource::Resource("network-transmit".to_string()))
+            .tooltip("Syncing")
+            .enabled(0, false))
+        .state("paused", StateBundle::new().label(1, "Resume"))
+        .state("broken", StateBundle::new().tooltip("Broken").enabled(7, false)));
+    assert_eq!(app.current_state(), None);
+
+    app.set_state("syncing").unwrap();
+    assert_eq!(mock.icon(), resource("network-transmit"));
+    assert_eq!(mock.tooltip(), "Syncing");
+    assert!(!mock.is_enabled(0));
+    assert_eq!(app.current_state(), Some("syncing".to_string()));
+
+    // Only what the bundle names changes.
+    app.handle().set_state("paused".to_string()).wait().unwrap();
+    assert_eq!(mock.icon(), resource("network-transmit"));
+    assert!(!mock.is_enabled(0));
+    assert_eq!(mock.menu()[1], MockMenuEntry::Item(1, "Resume".to_string()));
+
+    app.set_state("idle").unwrap();
+    assert_eq!(mock.icon(), resource("network-idle"));
+    assert_eq!(mock.tooltip(), "Up to date");
+    assert!(mock.is_enabled(0));
+    assert_eq!(mock.menu()[1], MockMenuEntry::Item(1, "Pause".to_string()));
+
+    // Nothing changes for unknown states or bundles naming unknown items.
+    match app.set_state("offline") {
+        Err(SystrayError::UnknownState(name)) => assert_eq!(name, "offline"),
+        _ => panic!("expected an unknown state"),
+    }
+    match app.set_state("broken") {
+        Err(SystrayError::UnknownMenuItem(7)) => {}
+        _ => panic!("expected an unknown menu item"),
+    }
+    assert_eq!(mock.tooltip(), "Up to date");
+    assert_eq!(app.current_state(), Some("idle".to_string()));
+}
+
+#[test]
+fn states_ask_for_attention_where_backends_can() {
+    let mock = MockTray::new();
+    let mut app = Application::builder()
+        .icon(IconResource::Resource("backup".to_string()))
+        .tooltip("Up to date")
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    app.set_states(TrayStates::new()
+        .state("idle", StateBundle::new().tooltip("Up to date").attention(false))
+        .state("failed", StateBundle::new()
+            .tooltip("Backup failed")
+            .attention_icon(IconResource::Resource("backup-failed".to_string()))
+            .attention(true)));
+
+    // Refused before anything changes.
+    match app.set_state("failed") {
+        Err(SystrayError::NotImplementedError) => {}
+        _ => panic!("expected NotImplementedError"),
+    }
+    assert_eq!(mock.tooltip(), "Up to date");
+    assert_eq!(app.current_state(), None);
+
+    mock.set_capabilities(Capabilities { attention: true, ..Capabilities::default() });
+    app.set_state("failed").unwrap();
+    assert!(mock.wants_attention());
+    assert_eq!(mock.attention_icon(), resource("backup-failed"));
+    assert_eq!(mock.tooltip(), "Backup failed");
+    app.set_state("idle").unwrap();
+    assert!(!mock.wants_attention());
+    assert_eq!(mock.attention_icon(), resource("backup-failed"));
+}
+
+#[test]
+fn states_roll_back_when_the_backend_fails() {
+    let mock = MockTray::new();
+    let mut app = Application::builder()
+        .icon(IconResource::Resource("network-idle".to_string()))
+        .tooltip("Up to date")
+        .menu(Menu::new().item_with("Sync now", 0))
+        .backend(Backend::Mock(mock.clone()))
+        .build()
+        .unwrap();
+    app.set_states(TrayStates::new()
+        .state("idle", StateBundle::new().tooltip("Up to date"))
+        .state("syncing", StateBundle::new()
+            .tooltip("Syncing")
+            .icon(IconResource::Resource("network-transmit".to_string()))
+            .enabled(0, false)));
+    app.set_state("idle").unwrap();
+
+    mock.fail_icons(true);
+    match app.set_state("syncing") {
+        Err(SystrayError::OsError(_)) => {}
+        _ => panic!("expected the icon to fail"),
+    }
+    assert_eq!(mock.tooltip(), "Up to date");
+    assert!(mock.is_enabled(0));
+    assert_eq!(mock.icon(), resource("network-idle"));
+    let state = app.state();
+    assert_eq!(state.tooltip, "Up to date");
+    assert_eq!(app.current_state(), Some("idle".to_string()));
+
+    mock.fail_icons(false);
+    app.set_state("syncing").unwrap();
+    assert_eq!(mock.icon(), resource("network-transmit"));
+    assert_eq!(app.current_state(), Some("syncing".to_string()));
+}